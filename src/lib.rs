@@ -1 +1 @@
-
+pub mod server;
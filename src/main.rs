@@ -1,5 +1,395 @@
-mod server;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-fn main() {
-    println!("Hello, world!");
+use bytes::Bytes;
+use clap::{Parser, Subcommand};
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{self, HeaderMap};
+use hyper::{client, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+
+use chronicle::server::config::{ServerConfig, TlsConfig, CONFIG_ENV};
+use chronicle::server::http;
+use chronicle::server::log::inspect::{self, Outcome};
+use chronicle::server::log::{Config, Log, SharedLog};
+
+// Settings are read from the config file, then `CHRONICLE_*` environment
+// variables, then the flags below, each overriding the ones before.
+#[derive(Parser)]
+#[command(version, about = "A persistent event-stream service")]
+struct Cli {
+    /// TOML file to read settings from
+    #[arg(long, global = true, env = CONFIG_ENV)]
+    config: Option<PathBuf>,
+    /// Directory the log is stored in [default: data]
+    #[arg(long, global = true)]
+    dir: Option<PathBuf>,
+    /// Address to serve on, may be repeated [default: 127.0.0.1:8080]
+    #[arg(long, global = true)]
+    listen: Vec<SocketAddr>,
+    /// Set any setting, such as `segment.max_store_bytes=1048576`
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_override)]
+    overrides: Vec<(String, String)>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the log over HTTP (the default)
+    Serve,
+    /// Have the running server write a snapshot of the log, up to and
+    /// including an offset
+    Snapshot {
+        /// Last offset to include [default: the whole log]
+        #[arg(long)]
+        upto: Option<u64>,
+        /// File to write the snapshot to [default: stdout]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Name the server's TLS certificate was issued for [default: localhost]
+        #[arg(long)]
+        server_name: Option<String>,
+    },
+    /// Rebuild the log directory from a snapshot
+    Restore {
+        /// File to read the snapshot from [default: stdin]
+        #[arg(long, short)]
+        input: Option<PathBuf>,
+    },
+    /// List the log's segments, or print the records in a range as JSON
+    Dump {
+        /// First offset to print
+        #[arg(long)]
+        from: Option<u64>,
+        /// Last offset to print
+        #[arg(long)]
+        to: Option<u64>,
+    },
+    /// Check the checksums and indexes of every segment
+    Verify,
+    /// Rebuild the indexes of the segments that fail verification
+    Repair {
+        /// Cut stores at their first corrupt frame even where readable
+        /// records follow it
+        #[arg(long)]
+        force: bool,
+    },
+    /// Inspect the settings the other commands run with
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the settings merged from every source as TOML
+    Print,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result: Result<(), Box<dyn Error>> =
+        load(&cli).and_then(|config| match cli.command.unwrap_or(Command::Serve) {
+            Command::Serve => serve(&config),
+            Command::Snapshot {
+                upto,
+                output,
+                server_name,
+            } => snapshot(&config, upto, output, server_name),
+            Command::Restore { input } => restore(&config, input),
+            Command::Dump {
+                from: None,
+                to: None,
+            } => list(&config),
+            Command::Dump { from, to } => dump(&config, from.unwrap_or(0), to.unwrap_or(u64::MAX)),
+            Command::Verify => verify(&config),
+            Command::Repair { force } => repair(&config, force),
+            Command::Config {
+                command: ConfigCommand::Print,
+            } => {
+                print!("{}", toml::to_string(&config)?);
+                Ok(())
+            }
+        });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_override(flag: &str) -> Result<(String, String), String> {
+    let (key, value) = flag
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, found `{flag}`"))?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+// The dedicated flags are applied after any `--set`, as the more specific.
+fn load(cli: &Cli) -> Result<ServerConfig, Box<dyn Error>> {
+    let mut overrides: Vec<(String, String)> = cli.overrides.clone();
+    if let Some(dir) = &cli.dir {
+        overrides.push(("data_dir".to_string(), dir.display().to_string()));
+    }
+    if !cli.listen.is_empty() {
+        let addrs: Vec<String> = cli.listen.iter().map(SocketAddr::to_string).collect();
+        overrides.push(("listen".to_string(), addrs.join(",")));
+    }
+    Ok(ServerConfig::load(
+        cli.config.as_deref(),
+        std::env::vars(),
+        &overrides,
+    )?)
+}
+
+fn open(config: &ServerConfig) -> Result<Log, Box<dyn Error>> {
+    Ok(Log::open(&config.data_dir, config.log_config()?)?)
+}
+
+fn serve(config: &ServerConfig) -> Result<(), Box<dyn Error>> {
+    let tls = config.tls.as_ref().map(TlsConfig::acceptor).transpose()?;
+    let runtime = Runtime::new()?;
+    let listeners: Vec<TcpListener> = runtime.block_on(async {
+        let mut listeners: Vec<TcpListener> = Vec::new();
+        for &addr in &config.listen {
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(|err| format!("failed to listen on {addr}: {err}"))?;
+            listeners.push(listener);
+        }
+        Ok::<_, String>(listeners)
+    })?;
+    let log = SharedLog::new(open(config)?);
+    runtime.block_on(http::serve(listeners, log, tls, async {
+        let _ = tokio::signal::ctrl_c().await;
+    }))?;
+    Ok(())
+}
+
+// Snapshots are taken by the server, which has the log open, and downloaded
+// from its first listen address.
+fn snapshot(
+    config: &ServerConfig,
+    upto: Option<u64>,
+    output: Option<PathBuf>,
+    server_name: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let mut addr: SocketAddr = *config.listen.first().ok_or("no listen address is set")?;
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    let target: String = match upto {
+        Some(upto) => format!("/admin/snapshot?upto={upto}"),
+        None => "/admin/snapshot".to_string(),
+    };
+    let runtime = Runtime::new()?;
+    let headers: HeaderMap = runtime.block_on(async {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|err| format!("failed to connect to the server on {addr}: {err}"))?;
+        match &config.tls {
+            Some(tls) => {
+                let name = ServerName::try_from(server_name.as_deref().unwrap_or("localhost"))?;
+                let stream = TlsConnector::from(tls.client()?)
+                    .connect(name.to_owned(), stream)
+                    .await?;
+                download(stream, addr, &target, output).await
+            }
+            None => download(stream, addr, &target, output).await,
+        }
+    })?;
+
+    let header = |name: &str| -> String {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("?")
+            .to_string()
+    };
+    eprintln!(
+        "wrote {} segments from offset {} up to {}",
+        header("chronicle-segments"),
+        header("chronicle-lowest-offset"),
+        header("chronicle-next-offset")
+    );
+    Ok(())
+}
+
+// Sends a GET request for `target` over `stream` and writes the body to
+// `output`, or stdout without it, returning the response's headers. Responses
+// other than 200 OK fail with the error the server gave.
+async fn download(
+    stream: impl AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    host: SocketAddr,
+    target: &str,
+    output: Option<PathBuf>,
+) -> Result<HeaderMap, Box<dyn Error>> {
+    let (mut sender, connection) = client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(connection);
+    let request = Request::get(target)
+        .header(header::HOST, host.to_string())
+        .body(Empty::<Bytes>::new())?;
+    let (response, mut body) = sender.send_request(request).await?.into_parts();
+
+    if response.status != StatusCode::OK {
+        let body = body.collect().await?.to_bytes();
+        let body = String::from_utf8_lossy(&body);
+        let error: String = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|json| json["error"].as_str().map(str::to_string))
+            .unwrap_or_else(|| body.into_owned());
+        return Err(format!("the server responded with {}: {error}", response.status).into());
+    }
+
+    let writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut writer = BufWriter::new(writer);
+    // hyper fails the body if the connection closes before all of it arrived.
+    while let Some(frame) = body.frame().await {
+        if let Ok(data) = frame?.into_data() {
+            writer.write_all(&data)?;
+        }
+    }
+    writer.flush()?;
+    Ok(response.headers)
+}
+
+fn restore(config: &ServerConfig, input: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let reader: Box<dyn Read> = match input {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin().lock()),
+    };
+    let log = Log::restore(
+        BufReader::new(reader),
+        &config.data_dir,
+        config.log_config()?,
+    )?;
+    let next_offset = log
+        .highest_offset()
+        .map_or(log.lowest_offset(), |highest| highest + 1);
+    log.close()?;
+    eprintln!("restored a log resuming at offset {next_offset}");
+    Ok(())
+}
+
+fn list(config: &ServerConfig) -> Result<(), Box<dyn Error>> {
+    for report in inspect::inspect(&config.data_dir, &config.log_config()?)? {
+        let contents: String = match (report.first_offset, report.last_offset) {
+            (Some(first), Some(last)) => format!(
+                "offsets {first} to {last}, {} records in {} batches",
+                report.records, report.batches
+            ),
+            _ => "empty".to_string(),
+        };
+        println!(
+            "segment {}: {contents}, {} bytes, format version {}",
+            report.base_offset, report.store_bytes, report.version
+        );
+    }
+    Ok(())
+}
+
+fn dump(config: &ServerConfig, from: u64, to: u64) -> Result<(), Box<dyn Error>> {
+    let log_config: Config = config.log_config()?;
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    inspect::dump(&config.data_dir, &log_config, from, to, |record| {
+        serde_json::to_writer(&mut stdout, &record).map_err(io::Error::other)?;
+        Ok(writeln!(stdout)?)
+    })?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn verify(config: &ServerConfig) -> Result<(), Box<dyn Error>> {
+    let mut failed: usize = 0;
+    for report in inspect::inspect(&config.data_dir, &config.log_config()?)? {
+        if report.problems.is_empty() {
+            println!("segment {}: ok", report.base_offset);
+            continue;
+        }
+        failed += 1;
+        for problem in report.problems {
+            println!("segment {}: {problem}", report.base_offset);
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{failed} segments failed verification").into());
+    }
+    Ok(())
+}
+
+fn repair(config: &ServerConfig, force: bool) -> Result<(), Box<dyn Error>> {
+    let repairs = inspect::repair(&config.data_dir, &config.log_config()?, force)?;
+    if repairs.is_empty() {
+        println!("nothing to repair");
+    }
+    let (mut skipped, mut unrepairable): (usize, usize) = (0, 0);
+    for repair in repairs {
+        let (before, after) = repair.store_bytes;
+        let dropped: String = match repair.dropped_offsets {
+            Some((first, last)) => format!(
+                "{} bytes and offsets {first} to {last}",
+                before.saturating_sub(after)
+            ),
+            None => format!("{} bytes", before.saturating_sub(after)),
+        };
+        let base_offset: u64 = repair.base_offset;
+        match repair.outcome {
+            Outcome::Unrepairable(reason) => {
+                unrepairable += 1;
+                println!("segment {base_offset}: left as it is, since its store cannot be read: {reason}");
+            }
+            Outcome::NeedsForce if repair.recreated => {
+                skipped += 1;
+                println!(
+                    "segment {base_offset}: left as it is, since its store is missing and an empty one in its place would hide that its records are lost"
+                );
+            }
+            Outcome::NeedsForce => {
+                skipped += 1;
+                println!(
+                    "segment {base_offset}: left as it is, since cutting the store at its corrupt frame would drop {dropped}"
+                );
+            }
+            Outcome::Applied if repair.recreated => {
+                println!("segment {base_offset}: rebuilt indexes over a new, empty store");
+            }
+            Outcome::Applied if before == after => {
+                println!("segment {base_offset}: rebuilt indexes");
+            }
+            Outcome::Applied => {
+                println!(
+                    "segment {base_offset}: rebuilt indexes and cut the store from {before} to {after} bytes, dropping {dropped}"
+                );
+            }
+        }
+    }
+    if unrepairable > 0 {
+        return Err(format!("{unrepairable} segments cannot be repaired").into());
+    }
+    if skipped > 0 {
+        return Err(format!(
+            "{skipped} segments were left unrepaired; pass --force to repair them anyway"
+        )
+        .into());
+    }
+    Ok(())
 }
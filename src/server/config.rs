@@ -0,0 +1,472 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::TlsAcceptor;
+use toml::{Table, Value};
+
+use super::log::config::duration;
+use super::log::index::ENT_WIDTH;
+use super::log::tiered::LocalObjectStore;
+use super::log::{
+    CompactionConfig, Compression, Config, EncryptionConfig, RetentionConfig, SegmentConfig,
+    SyncPolicy, TieringConfig,
+};
+
+// Settings are layered, each layer overriding the ones before it: built-in
+// defaults, then the TOML config file, then `CHRONICLE_*` environment
+// variables, then command line flags. A variable is named after the key it
+// sets, so `CHRONICLE_SEGMENT_MAX_STORE_BYTES` sets `segment.max_store_bytes`.
+pub const ENV_PREFIX: &str = "CHRONICLE_";
+
+// Names the config file when no `--config` flag is given. It is not a
+// setting itself, so it is passed over when the environment is read.
+pub const CONFIG_ENV: &str = "CHRONICLE_CONFIG";
+
+// Everything a node is started with. Sections of the file mirror the log's
+// `Config`, with durations written as `90s` or `7days`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // Addresses the HTTP API listens on.
+    pub listen: Vec<SocketAddr>,
+    // Directory the log is stored in.
+    pub data_dir: PathBuf,
+    #[serde(with = "text")]
+    pub sync: SyncPolicy,
+    #[serde(with = "text")]
+    pub compression: Compression,
+    pub segment: SegmentConfig,
+    pub retention: RetentionConfig,
+    pub compaction: CompactionConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tiering: Option<TieringSettings>,
+    // The API is served over plain HTTP unless this is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let config: Config = Config::default();
+        Self {
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 8080))],
+            data_dir: PathBuf::from("data"),
+            sync: config.sync,
+            compression: config.compression,
+            segment: config.segment,
+            retention: config.retention,
+            compaction: config.compaction,
+            encryption: None,
+            tiering: None,
+            tls: None,
+        }
+    }
+}
+
+// Tiering to an object store kept in a local directory, such as a mounted
+// network share.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TieringSettings {
+    pub dir: PathBuf,
+    #[serde(with = "duration")]
+    pub hot_retention: Duration,
+    #[serde(with = "duration", default = "default_check_interval")]
+    pub check_interval: Duration,
+}
+
+fn default_check_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+// PEM files holding the server's certificate chain and private key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    // Loads the certificate chain and key for accepting TLS connections.
+    pub fn acceptor(&self) -> Result<TlsAcceptor, ConfigError> {
+        let certs: Vec<CertificateDer<'static>> = self.certs()?;
+        let key: PrivateKeyDer<'static> = PrivateKeyDer::from_pem_slice(&read(&self.key_path)?)
+            .map_err(|err| invalid(&self.key_path, err.to_string()))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut server = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(|err| invalid(&self.key_path, err.to_string()))?;
+        server.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(server)))
+    }
+
+    // Settings for connecting to the server this configures, as the CLI
+    // does, trusting the certificates in its chain.
+    pub fn client(&self) -> Result<Arc<rustls::ClientConfig>, ConfigError> {
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in self.certs()? {
+            roots
+                .add(cert)
+                .map_err(|err| invalid(&self.cert_path, err.to_string()))?;
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let client = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|err| invalid(&self.cert_path, err.to_string()))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Arc::new(client))
+    }
+
+    fn certs(&self) -> Result<Vec<CertificateDer<'static>>, ConfigError> {
+        let certs: Vec<CertificateDer<'static>> =
+            CertificateDer::pem_slice_iter(&read(&self.cert_path)?)
+                .collect::<Result<_, _>>()
+                .map_err(|err| invalid(&self.cert_path, err.to_string()))?;
+        if certs.is_empty() {
+            return Err(invalid(
+                &self.cert_path,
+                "no certificates found".to_string(),
+            ));
+        }
+        Ok(certs)
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, ConfigError> {
+    fs::read(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn invalid(path: &Path, message: String) -> ConfigError {
+    ConfigError::Parse {
+        origin: path.display().to_string(),
+        message,
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // A file named by the configuration could not be read or created.
+    Io { path: PathBuf, source: io::Error },
+    // A setting is unknown or holds a value of the wrong form. `origin`
+    // names where it came from: the file, a variable or a flag.
+    Parse { origin: String, message: String },
+    // Settings that parse but cannot be started with, all listed at once.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            ConfigError::Parse { origin, message } => write!(f, "{origin}: {message}"),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration: {}", problems.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// How a value given as text, in a variable or a flag, is turned into TOML.
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Integer,
+    Boolean,
+    // A comma-separated list of strings.
+    List,
+}
+
+// Every key that can be set from the environment or the command line.
+const KEYS: &[(&str, Kind)] = &[
+    ("listen", Kind::List),
+    ("data_dir", Kind::String),
+    ("sync", Kind::String),
+    ("compression", Kind::String),
+    ("segment.max_store_bytes", Kind::Integer),
+    ("segment.max_index_bytes", Kind::Integer),
+    ("segment.initial_offset", Kind::Integer),
+    ("retention.max_bytes", Kind::Integer),
+    ("retention.max_age", Kind::String),
+    ("retention.check_interval", Kind::String),
+    ("compaction.enabled", Kind::Boolean),
+    ("compaction.tombstone_retention", Kind::String),
+    ("compaction.check_interval", Kind::String),
+    ("encryption.key_file", Kind::String),
+    ("encryption.active_key_id", Kind::Integer),
+    ("tiering.dir", Kind::String),
+    ("tiering.hot_retention", Kind::String),
+    ("tiering.check_interval", Kind::String),
+    ("tls.cert_path", Kind::String),
+    ("tls.key_path", Kind::String),
+];
+
+impl ServerConfig {
+    // Merges the config file at `file`, the `CHRONICLE_*` variables among
+    // `env` and the `key=value` pairs in `overrides`, in that order, over the
+    // defaults, then checks the result can be started with.
+    pub fn load(
+        file: Option<&Path>,
+        env: impl IntoIterator<Item = (String, String)>,
+        overrides: &[(String, String)],
+    ) -> Result<Self, ConfigError> {
+        let mut table: Table = match file {
+            Some(path) => {
+                let contents: String =
+                    fs::read_to_string(path).map_err(|source| ConfigError::Io {
+                        path: path.to_path_buf(),
+                        source,
+                    })?;
+                contents
+                    .parse()
+                    .map_err(|err: toml::de::Error| ConfigError::Parse {
+                        origin: path.display().to_string(),
+                        message: err.to_string(),
+                    })?
+            }
+            None => Table::new(),
+        };
+
+        let mut vars: Vec<(String, String)> = env
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX) && name != CONFIG_ENV)
+            .collect();
+        vars.sort();
+        for (name, text) in vars {
+            // The environment is shared with whatever else runs alongside, so
+            // a variable that sets nothing is only warned about.
+            let Some(key) = KEYS
+                .iter()
+                .map(|&(key, _)| key)
+                .find(|key| env_name(key) == name)
+            else {
+                eprintln!("warning: ignoring {name}, which is not a setting");
+                continue;
+            };
+            set(&mut table, key, &text, &name)?;
+        }
+        for (key, text) in overrides {
+            set(&mut table, key, text, &format!("--set {key}"))?;
+        }
+
+        let config: ServerConfig =
+            Value::Table(table)
+                .try_into()
+                .map_err(|err: toml::de::Error| ConfigError::Parse {
+                    origin: "configuration".to_string(),
+                    // Names the offending key on a line of its own.
+                    message: err.to_string().trim_end().replace('\n', " "),
+                })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Lists every problem that would keep a node from starting with these
+    // settings.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems: Vec<String> = Vec::new();
+        if self.listen.is_empty() {
+            problems.push("listen must name at least one address".to_string());
+        }
+        if self.data_dir.as_os_str().is_empty() {
+            problems.push("data_dir must not be empty".to_string());
+        }
+        match self.sync {
+            SyncPolicy::EveryRecords(0) => {
+                problems.push("sync must fsync after at least one record".to_string())
+            }
+            SyncPolicy::Interval(Duration::ZERO) => {
+                problems.push("sync interval must be greater than zero".to_string())
+            }
+            _ => {}
+        }
+        if let Compression::Zstd(level) = self.compression {
+            let levels = zstd::compression_level_range();
+            if !levels.contains(&level) {
+                problems.push(format!(
+                    "zstd level {level} is outside {} to {}",
+                    levels.start(),
+                    levels.end()
+                ));
+            }
+        }
+        if self.segment.max_store_bytes == 0 {
+            problems.push("segment.max_store_bytes must be greater than zero".to_string());
+        }
+        if self.segment.max_index_bytes < ENT_WIDTH
+            || !self.segment.max_index_bytes.is_multiple_of(ENT_WIDTH)
+        {
+            problems.push(format!(
+                "segment.max_index_bytes must be a positive multiple of {ENT_WIDTH}, the size of an index entry"
+            ));
+        }
+        // Offsets within a segment are indexed as 32 bits.
+        let max_index_bytes: u64 = u32::MAX as u64 * ENT_WIDTH;
+        if self.segment.max_index_bytes > max_index_bytes {
+            problems.push(format!(
+                "segment.max_index_bytes must be at most {max_index_bytes}, enough for {} entries",
+                u32::MAX
+            ));
+        }
+        let mut intervals: Vec<(&str, Duration)> = vec![
+            ("retention.check_interval", self.retention.check_interval),
+            ("compaction.check_interval", self.compaction.check_interval),
+        ];
+        if let Some(tiering) = &self.tiering {
+            intervals.push(("tiering.check_interval", tiering.check_interval));
+        }
+        for (key, interval) in intervals {
+            if interval.is_zero() {
+                problems.push(format!("{key} must be greater than zero"));
+            }
+        }
+        let mut files: Vec<(&str, &Path)> = Vec::new();
+        if let Some(encryption) = &self.encryption {
+            files.push(("encryption.key_file", &encryption.key_file));
+        }
+        if let Some(tls) = &self.tls {
+            files.push(("tls.cert_path", &tls.cert_path));
+            files.push(("tls.key_path", &tls.key_path));
+        }
+        for (key, path) in files {
+            if !path.is_file() {
+                problems.push(format!("{key} {} is not a file", path.display()));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    // The log's configuration. Tiering creates the object store's directory
+    // if it does not exist yet.
+    pub fn log_config(&self) -> Result<Config, ConfigError> {
+        let tiering: Option<TieringConfig> = match &self.tiering {
+            Some(tiering) => Some(TieringConfig {
+                store: Arc::new(LocalObjectStore::new(&tiering.dir).map_err(|source| {
+                    ConfigError::Io {
+                        path: tiering.dir.clone(),
+                        source,
+                    }
+                })?),
+                hot_retention: tiering.hot_retention,
+                check_interval: tiering.check_interval,
+            }),
+            None => None,
+        };
+        Ok(Config {
+            segment: self.segment.clone(),
+            sync: self.sync.clone(),
+            retention: self.retention.clone(),
+            compaction: self.compaction.clone(),
+            compression: self.compression.clone(),
+            encryption: self.encryption.clone(),
+            tiering,
+        })
+    }
+}
+
+// The variable that sets `key`.
+pub fn env_name(key: &str) -> String {
+    format!("{ENV_PREFIX}{}", key.replace('.', "_").to_uppercase())
+}
+
+// Sets `key` in `table` to `text` read as the key's kind, creating the
+// sections on the way.
+fn set(table: &mut Table, key: &str, text: &str, origin: &str) -> Result<(), ConfigError> {
+    let parse_error = |message: String| ConfigError::Parse {
+        origin: origin.to_string(),
+        message,
+    };
+    let kind: Kind = KEYS
+        .iter()
+        .find(|&&(known, _)| known == key)
+        .map(|&(_, kind)| kind)
+        .ok_or_else(|| parse_error("unknown setting".to_string()))?;
+    let value: Value = match kind {
+        Kind::String => Value::String(text.to_string()),
+        Kind::Integer => text
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| parse_error(format!("expected an integer, found `{text}`")))?,
+        Kind::Boolean => text
+            .parse()
+            .map(Value::Boolean)
+            .map_err(|_| parse_error(format!("expected true or false, found `{text}`")))?,
+        Kind::List => Value::Array(
+            text.split(',')
+                .map(|item| Value::String(item.trim().to_string()))
+                .filter(|item| item.as_str() != Some(""))
+                .collect(),
+        ),
+    };
+
+    let (sections, name) = match key.rsplit_once('.') {
+        Some((sections, name)) => (sections.split('.').collect(), name),
+        None => (Vec::new(), key),
+    };
+    let mut table: &mut Table = table;
+    for section in sections {
+        let entry: &mut Value = table
+            .entry(section)
+            .or_insert_with(|| Value::Table(Table::new()));
+        table = entry
+            .as_table_mut()
+            .ok_or_else(|| parse_error(format!("`{section}` is not a section")))?;
+    }
+    table.insert(name.to_string(), value);
+    Ok(())
+}
+
+// Settings with many forms, such as the sync policy, are written as the text
+// their `Display` and `FromStr` implementations agree on.
+mod text {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr<Err = String>,
+        D: Deserializer<'de>,
+    {
+        let text: String = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -1 +1,484 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::{self, JoinSet};
+use tokio_rustls::TlsAcceptor;
+use warp::http::header::{self, HeaderName, HeaderValue};
+use warp::http::StatusCode;
+use warp::reply::{self, Reply, Response};
+use warp::{Filter, Rejection};
+
+use super::log::{Error, Record, SharedLog, Stats};
+
+#[derive(Deserialize)]
+pub struct ProduceRequest {
+    pub record: Record,
+}
+
+#[derive(Serialize)]
+pub struct ProduceResponse {
+    pub offset: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ProduceBatchRequest {
+    pub records: Vec<Record>,
+}
+
+#[derive(Serialize)]
+pub struct ProduceBatchResponse {
+    pub first_offset: u64,
+    pub last_offset: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ConsumeRequest {
+    pub offset: u64,
+}
+
+#[derive(Serialize)]
+pub struct ConsumeResponse {
+    pub record: Record,
+}
+
+#[derive(Deserialize)]
+pub struct ConsumeRangeRequest {
+    pub offset: u64,
+    #[serde(default = "default_max_records")]
+    pub max_records: usize,
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct ConsumeRangeResponse {
+    pub records: Vec<Record>,
+    pub next_offset: u64,
+}
+
+// The timestamp is either milliseconds since the Unix epoch or an RFC 3339
+// date such as `2026-10-01T00:00:00Z`.
+#[derive(Deserialize)]
+pub struct SeekRequest {
+    pub timestamp: String,
+}
+
+#[derive(Serialize)]
+pub struct SeekResponse {
+    pub offset: u64,
+}
+
+#[derive(Deserialize)]
+pub struct TruncateRequest {
+    pub lowest: u64,
+}
+
+// Without `upto` the snapshot covers the whole log.
+#[derive(Deserialize)]
+pub struct SnapshotRequest {
+    pub upto: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct OffsetsResponse {
+    pub lowest_offset: u64,
+    pub highest_offset: Option<u64>,
+}
+
+fn default_max_records() -> usize {
+    100
+}
+
+fn default_max_bytes() -> u64 {
+    1024 * 1024
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+// Serves the log on every listener, over TLS when given an acceptor, until
+// `shutdown` completes. Requests in flight are then allowed to finish and
+// the log is closed so buffered records reach disk.
+pub async fn serve(
+    listeners: Vec<TcpListener>,
+    log: SharedLog,
+    tls: Option<TlsAcceptor>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), Error> {
+    let (stop, stopped) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown.await;
+        let _ = stop.send(true);
+    });
+    let mut servers: JoinSet<()> = JoinSet::new();
+    for listener in listeners {
+        let mut stopped = stopped.clone();
+        let stopped = async move {
+            let _ = stopped.wait_for(|&stop| stop).await;
+        };
+        match &tls {
+            Some(tls) => servers.spawn(serve_tls(listener, log.clone(), tls.clone(), stopped)),
+            None => servers.spawn(
+                warp::serve(routes(log.clone()))
+                    .incoming(listener)
+                    .graceful(stopped)
+                    .run(),
+            ),
+        };
+    }
+    while servers.join_next().await.is_some() {}
+    log.close()
+}
+
+// Warp only serves plain HTTP, so TLS connections are accepted here and
+// handed to hyper with the same routes. Both HTTP/1.1 and HTTP/2 are served,
+// as negotiated through ALPN.
+async fn serve_tls(
+    listener: TcpListener,
+    log: SharedLog,
+    tls: TlsAcceptor,
+    stopped: impl Future<Output = ()>,
+) {
+    let service = TowerToHyperService::new(warp::service(routes(log)));
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(stopped);
+    loop {
+        let stream: TcpStream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                // Most likely out of file descriptors, which frees up as
+                // connections close.
+                Err(_) => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            () = &mut stopped => break,
+        };
+        let (tls, service, builder) = (tls.clone(), service.clone(), builder.clone());
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            // Connections that fail the handshake are dropped.
+            let Ok(stream) = tls.accept(stream).await else {
+                return;
+            };
+            let connection = builder.serve_connection(TokioIo::new(stream), service);
+            let _ = watcher.watch(connection.into_owned()).await;
+        });
+    }
+    graceful.shutdown().await;
+}
+
+pub fn routes(log: SharedLog) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let produce = warp::post()
+        .and(warp::path::end())
+        .and(with_log(log.clone()))
+        .and(warp::body::json())
+        .and_then(handle_produce);
+
+    let produce_batch = warp::post()
+        .and(warp::path!("batch"))
+        .and(with_log(log.clone()))
+        .and(warp::body::json())
+        .and_then(handle_produce_batch);
+
+    let consume = warp::get()
+        .and(warp::path::end())
+        .and(with_log(log.clone()))
+        .and(warp::query::<ConsumeRequest>())
+        .and_then(handle_consume);
+
+    let consume_range = warp::get()
+        .and(warp::path!("records"))
+        .and(with_log(log.clone()))
+        .and(warp::query::<ConsumeRangeRequest>())
+        .and_then(handle_consume_range);
+
+    let consume_value = warp::get()
+        .and(warp::path!("value"))
+        .and(with_log(log.clone()))
+        .and(warp::query::<ConsumeRequest>())
+        .and_then(handle_consume_value);
+
+    let seek = warp::get()
+        .and(warp::path("offset"))
+        .and(warp::path::end())
+        .and(with_log(log.clone()))
+        .and(warp::query::<SeekRequest>())
+        .and_then(handle_seek);
+
+    let offsets = warp::get()
+        .and(warp::path!("admin" / "offsets"))
+        .and(with_log(log.clone()))
+        .and_then(handle_offsets);
+
+    let stats = warp::get()
+        .and(warp::path!("admin" / "stats"))
+        .and(with_log(log.clone()))
+        .and_then(handle_stats);
+
+    let snapshot = warp::get()
+        .and(warp::path!("admin" / "snapshot"))
+        .and(with_log(log.clone()))
+        .and(warp::query::<SnapshotRequest>())
+        .and_then(handle_snapshot);
+
+    let truncate = warp::post()
+        .and(warp::path!("admin" / "truncate"))
+        .and(with_log(log))
+        .and(warp::body::json())
+        .and_then(handle_truncate);
+
+    produce
+        .or(produce_batch)
+        .or(consume)
+        .or(consume_range)
+        .or(consume_value)
+        .or(seek)
+        .or(offsets)
+        .or(stats)
+        .or(snapshot)
+        .or(truncate)
+}
+
+fn with_log(log: SharedLog) -> impl Filter<Extract = (SharedLog,), Error = Infallible> + Clone {
+    warp::any().map(move || log.clone())
+}
+
+async fn handle_produce(log: SharedLog, req: ProduceRequest) -> Result<Response, Infallible> {
+    let result = task::spawn_blocking(move || log.append(req.record))
+        .await
+        .expect("append task panicked");
+    match result {
+        Ok(offset) => Ok(reply::json(&ProduceResponse { offset }).into_response()),
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+async fn handle_produce_batch(
+    log: SharedLog,
+    req: ProduceBatchRequest,
+) -> Result<Response, Infallible> {
+    let result = task::spawn_blocking(move || log.append_batch(req.records))
+        .await
+        .expect("append task panicked");
+    match result {
+        Ok((first_offset, last_offset)) => Ok(reply::json(&ProduceBatchResponse {
+            first_offset,
+            last_offset,
+        })
+        .into_response()),
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+async fn handle_consume(log: SharedLog, req: ConsumeRequest) -> Result<Response, Infallible> {
+    let result = task::spawn_blocking(move || log.read(req.offset))
+        .await
+        .expect("read task panicked");
+    match result {
+        Ok(record) => Ok(reply::json(&ConsumeResponse { record }).into_response()),
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+async fn handle_consume_range(
+    log: SharedLog,
+    req: ConsumeRangeRequest,
+) -> Result<Response, Infallible> {
+    let result =
+        task::spawn_blocking(move || log.read_range(req.offset, req.max_records, req.max_bytes))
+            .await
+            .expect("read task panicked");
+    match result {
+        Ok((records, next_offset)) => Ok(reply::json(&ConsumeRangeResponse {
+            records,
+            next_offset,
+        })
+        .into_response()),
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+// Serves a record's value as the raw response body. The body shares the
+// log's buffer for the value instead of copying it.
+async fn handle_consume_value(log: SharedLog, req: ConsumeRequest) -> Result<Response, Infallible> {
+    let result = task::spawn_blocking(move || log.read(req.offset))
+        .await
+        .expect("read task panicked");
+    match result {
+        Ok(record) => {
+            // A tombstone has no value to send back.
+            let mut res = match record.value {
+                Some(value) => {
+                    let mut res = Response::new(value.into());
+                    res.headers_mut().insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/octet-stream"),
+                    );
+                    res
+                }
+                None => reply::with_status(reply::reply(), StatusCode::NO_CONTENT).into_response(),
+            };
+            let headers = res.headers_mut();
+            headers.insert("chronicle-offset", HeaderValue::from(record.offset));
+            if let Some(key) = record.key {
+                let key: String = STANDARD.encode(key);
+                let key = HeaderValue::from_str(&key).expect("base64 is a valid header value");
+                headers.insert("chronicle-key", key);
+            }
+            // Record headers repeat as often as the record carries them. Keys
+            // that cannot form an HTTP header name are left out; the JSON
+            // endpoints return them all.
+            for record_header in record.headers {
+                let name = format!("chronicle-header-{}", record_header.key);
+                let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+                    continue;
+                };
+                let value: String = STANDARD.encode(record_header.value);
+                let value = HeaderValue::from_str(&value).expect("base64 is a valid header value");
+                headers.append(name, value);
+            }
+            Ok(res)
+        }
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+async fn handle_seek(log: SharedLog, req: SeekRequest) -> Result<Response, Infallible> {
+    let Some(timestamp) = parse_timestamp(&req.timestamp) else {
+        let error = format!("invalid timestamp {:?}", req.timestamp);
+        let body = reply::json(&ErrorResponse { error });
+        return Ok(reply::with_status(body, StatusCode::BAD_REQUEST).into_response());
+    };
+    let result = task::spawn_blocking(move || log.offset_for_time(timestamp))
+        .await
+        .expect("seek task panicked");
+    match result {
+        Ok(offset) => Ok(reply::json(&SeekResponse { offset }).into_response()),
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+// Converts a timestamp given as epoch milliseconds or an RFC 3339 date into
+// epoch milliseconds.
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    if let Ok(millis) = timestamp.parse::<u64>() {
+        return Some(millis);
+    }
+    let time: SystemTime = humantime::parse_rfc3339_weak(timestamp).ok()?;
+    let since: Duration = time.duration_since(UNIX_EPOCH).ok()?;
+    Some(since.as_millis() as u64)
+}
+
+async fn handle_offsets(log: SharedLog) -> Result<Response, Infallible> {
+    let result = task::spawn_blocking(move || offsets(&log))
+        .await
+        .expect("offsets task panicked");
+    match result {
+        Ok(offsets) => Ok(reply::json(&offsets).into_response()),
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+async fn handle_stats(log: SharedLog) -> Result<Response, Infallible> {
+    let result: Result<Stats, Error> = task::spawn_blocking(move || log.stats())
+        .await
+        .expect("stats task panicked");
+    match result {
+        Ok(stats) => Ok(reply::json(&stats).into_response()),
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+// Serves a snapshot archive as the raw response body, with its manifest's
+// offsets and segment count in headers. The archive is spooled to disk
+// first, so the log is only locked while the snapshot is taken.
+async fn handle_snapshot(log: SharedLog, req: SnapshotRequest) -> Result<Response, Infallible> {
+    let result = task::spawn_blocking(move || log.snapshot(req.upto.unwrap_or(u64::MAX))?.spool())
+        .await
+        .expect("snapshot task panicked");
+    match result {
+        Ok((manifest, archive)) => {
+            let mut res = Response::new(archive.into());
+            let headers = res.headers_mut();
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/x-tar"),
+            );
+            headers.insert(
+                "chronicle-lowest-offset",
+                HeaderValue::from(manifest.lowest_offset),
+            );
+            headers.insert(
+                "chronicle-next-offset",
+                HeaderValue::from(manifest.next_offset),
+            );
+            headers.insert(
+                "chronicle-segments",
+                HeaderValue::from(manifest.segments.len()),
+            );
+            Ok(res)
+        }
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+// Deletes the segments below `lowest` and reports the offsets still held.
+async fn handle_truncate(log: SharedLog, req: TruncateRequest) -> Result<Response, Infallible> {
+    let result = task::spawn_blocking(move || {
+        log.truncate(req.lowest)?;
+        offsets(&log)
+    })
+    .await
+    .expect("truncate task panicked");
+    match result {
+        Ok(offsets) => Ok(reply::json(&offsets).into_response()),
+        Err(err) => Ok(error_reply(err)),
+    }
+}
+
+fn offsets(log: &SharedLog) -> Result<OffsetsResponse, Error> {
+    Ok(OffsetsResponse {
+        lowest_offset: log.lowest_offset()?,
+        highest_offset: log.highest_offset()?,
+    })
+}
+
+// Maps each kind of log error onto the HTTP status clients should see.
+pub fn status_code(error: &Error) -> StatusCode {
+    match error {
+        Error::OffsetOutOfRange(_) => StatusCode::NOT_FOUND,
+        Error::OffsetTruncated { .. } | Error::Compacted { .. } => StatusCode::GONE,
+        Error::Closed => StatusCode::SERVICE_UNAVAILABLE,
+        Error::SegmentFull { .. } => StatusCode::INSUFFICIENT_STORAGE,
+        Error::EmptyBatch => StatusCode::BAD_REQUEST,
+        Error::BatchTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        Error::Corrupt { .. }
+        | Error::SegmentOverlap { .. }
+        | Error::UnsupportedVersion { .. }
+        | Error::MissingKey { .. }
+        | Error::Decryption { .. }
+        | Error::Locked { .. }
+        | Error::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn error_reply(error: Error) -> Response {
+    let status: StatusCode = status_code(&error);
+    let error: String = error.to_string();
+    reply::with_status(reply::json(&ErrorResponse { error }), status).into_response()
+}
@@ -0,0 +1,154 @@
+use bytes::Bytes;
+
+use super::{Header, Record};
+
+// Records are persisted in batches, one batch per store frame, so a batch is
+// written (or torn by a crash) as a single unit. A batch starts with the
+// offset of its first record (big-endian u64), the append timestamp shared by
+// all of its records (big-endian u64) and the number of records (big-endian
+// u32). Each record follows as its offset relative to the first (big-endian
+// u32), a flag byte saying whether an event time follows (big-endian u64), a
+// flag byte saying whether a key follows, the number of headers (big-endian
+// u32), each header's key and value, and finally a flag byte saying whether
+// a value follows. Keys, header keys, header values and the value are each
+// prefixed with their length (big-endian u32).
+const HEADER_WIDTH: usize = 8 + 8 + 4;
+
+pub fn encode(records: &[Record]) -> Vec<u8> {
+    let base_offset: u64 = records.first().map_or(0, |record| record.offset);
+    let timestamp: u64 = records.first().map_or(0, |record| record.timestamp);
+    let len: usize = records.iter().map(encoded_len).sum();
+
+    let mut data: Vec<u8> = Vec::with_capacity(HEADER_WIDTH + len);
+    data.extend_from_slice(&base_offset.to_be_bytes());
+    data.extend_from_slice(&timestamp.to_be_bytes());
+    data.extend_from_slice(&(records.len() as u32).to_be_bytes());
+    for record in records {
+        data.extend_from_slice(&((record.offset - base_offset) as u32).to_be_bytes());
+        match record.event_time {
+            Some(event_time) => {
+                data.push(1);
+                data.extend_from_slice(&event_time.to_be_bytes());
+            }
+            None => data.push(0),
+        }
+        match &record.key {
+            Some(key) => {
+                data.push(1);
+                put_bytes(&mut data, key);
+            }
+            None => data.push(0),
+        }
+        data.extend_from_slice(&(record.headers.len() as u32).to_be_bytes());
+        for header in &record.headers {
+            put_bytes(&mut data, header.key.as_bytes());
+            put_bytes(&mut data, &header.value);
+        }
+        match &record.value {
+            Some(value) => {
+                data.push(1);
+                put_bytes(&mut data, value);
+            }
+            None => data.push(0),
+        }
+    }
+    data
+}
+
+fn put_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    data.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    data.extend_from_slice(bytes);
+}
+
+fn encoded_len(record: &Record) -> usize {
+    let key: usize = record.key.as_ref().map_or(0, |key| 4 + key.len());
+    let headers: usize = record
+        .headers
+        .iter()
+        .map(|header| 8 + header.key.len() + header.value.len())
+        .sum();
+    let value: usize = record.value.as_ref().map_or(0, |value| 4 + value.len());
+    4 + 9 + 1 + key + 4 + headers + 1 + value
+}
+
+// Returns None if the data is not a well-formed batch. Record values are
+// slices of `data` rather than copies of it.
+pub fn decode(data: &Bytes) -> Option<Vec<Record>> {
+    let mut reader = Reader { data, pos: 0 };
+    let base_offset: u64 = reader.u64()?;
+    let timestamp: u64 = reader.u64()?;
+    let count: u32 = reader.u32()?;
+
+    let mut records: Vec<Record> = Vec::with_capacity(count.min(1024) as usize);
+    for _ in 0..count {
+        let offset: u64 = base_offset + reader.u32()? as u64;
+        let event_time: Option<u64> = match reader.u8()? {
+            0 => None,
+            1 => Some(reader.u64()?),
+            _ => return None,
+        };
+        let key: Option<Bytes> = match reader.u8()? {
+            0 => None,
+            1 => Some(reader.sized_bytes()?),
+            _ => return None,
+        };
+        let header_count: u32 = reader.u32()?;
+        let mut headers: Vec<Header> = Vec::with_capacity(header_count.min(64) as usize);
+        for _ in 0..header_count {
+            let key: String = String::from_utf8(reader.sized_bytes()?.to_vec()).ok()?;
+            let value: Bytes = reader.sized_bytes()?;
+            headers.push(Header { key, value });
+        }
+        let value: Option<Bytes> = match reader.u8()? {
+            0 => None,
+            1 => Some(reader.sized_bytes()?),
+            _ => return None,
+        };
+        records.push(Record {
+            value,
+            offset,
+            timestamp,
+            event_time,
+            key,
+            headers,
+        });
+    }
+    (reader.pos == data.len()).then_some(records)
+}
+
+struct Reader<'a> {
+    data: &'a Bytes,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let taken: &'a [u8] = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(taken)
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<Bytes> {
+        let start: usize = self.pos;
+        self.take(len)?;
+        Some(self.data.slice(start..self.pos))
+    }
+
+    // Reads a length-prefixed run of bytes.
+    fn sized_bytes(&mut self) -> Option<Bytes> {
+        let len: u32 = self.u32()?;
+        self.bytes(len as usize)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::config::Config;
+use super::encryption::Keyring;
+use super::error::Error;
+use super::segment::Segment;
+use super::tiered::ObjectStore;
+
+// How many offloaded segments are kept once fetched back for reads.
+pub const CACHED_SEGMENTS: usize = 4;
+
+// Every fetch downloads into a subdirectory of its own, named after this
+// counter, so fetches running side by side never share files.
+static NEXT_FETCH: AtomicU64 = AtomicU64::new(0);
+
+// Offloaded segments fetched back from the object store for reads. The most
+// recently used ones are kept, up to `CACHED_SEGMENTS`. Fetches happen
+// outside the cache's lock, so a slow download holds up no other read.
+pub struct SegmentCache {
+    dir: PathBuf,
+    objects: Arc<dyn ObjectStore>,
+    config: Config,
+    keys: Arc<Keyring>,
+    // Least recently used first.
+    segments: Mutex<Vec<Arc<Fetched>>>,
+}
+
+impl SegmentCache {
+    pub fn new(
+        dir: PathBuf,
+        objects: Arc<dyn ObjectStore>,
+        config: Config,
+        keys: Arc<Keyring>,
+    ) -> Self {
+        Self {
+            dir,
+            objects,
+            config,
+            keys,
+            segments: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Returns the offloaded segment starting at `base_offset`, fetching it
+    // unless it is cached.
+    pub fn get(&self, base_offset: u64) -> Result<Arc<Fetched>, Error> {
+        {
+            let mut segments = self.segments.lock().expect("segment cache lock poisoned");
+            if let Some(index) = segments
+                .iter()
+                .position(|fetched| fetched.base_offset() == base_offset)
+            {
+                let fetched: Arc<Fetched> = segments.remove(index);
+                segments.push(fetched.clone());
+                return Ok(fetched);
+            }
+        }
+
+        let dir: PathBuf = self
+            .dir
+            .join(NEXT_FETCH.fetch_add(1, Ordering::Relaxed).to_string());
+        let segment: Segment = match Segment::fetch(
+            self.objects.as_ref(),
+            &dir,
+            base_offset,
+            &self.config,
+            &self.keys,
+        ) {
+            Ok(segment) => segment,
+            Err(err) => {
+                // Whatever part of the segment made it down.
+                let _ = fs::remove_dir_all(&dir);
+                return Err(err);
+            }
+        };
+        let fetched = Arc::new(Fetched {
+            segment: Some(segment),
+            dir,
+        });
+
+        let mut segments = self.segments.lock().expect("segment cache lock poisoned");
+        // Another read may have fetched the same segment meanwhile.
+        segments.retain(|cached| cached.base_offset() != base_offset);
+        segments.push(fetched.clone());
+        if segments.len() > CACHED_SEGMENTS {
+            segments.remove(0);
+        }
+        Ok(fetched)
+    }
+
+    // Forgets a segment deleted from the object store.
+    pub fn evict(&self, base_offset: u64) {
+        self.segments
+            .lock()
+            .expect("segment cache lock poisoned")
+            .retain(|fetched| fetched.base_offset() != base_offset);
+    }
+
+    pub fn clear(&self) {
+        self.segments
+            .lock()
+            .expect("segment cache lock poisoned")
+            .clear();
+    }
+}
+
+// An offloaded segment fetched for reads. Its files are deleted once the
+// cache has let go of it and no read is still using it.
+pub struct Fetched {
+    segment: Option<Segment>,
+    dir: PathBuf,
+}
+
+impl Deref for Fetched {
+    type Target = Segment;
+
+    fn deref(&self) -> &Segment {
+        self.segment.as_ref().expect("fetched segment is open")
+    }
+}
+
+impl Drop for Fetched {
+    fn drop(&mut self) {
+        if let Some(segment) = self.segment.take() {
+            let _ = segment.remove();
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
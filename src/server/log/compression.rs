@@ -0,0 +1,49 @@
+use std::io;
+
+use bytes::Bytes;
+
+use super::config::Compression;
+
+// From format version 3 on, every frame starts with a byte naming the codec
+// its batch was compressed with, followed by the compressed batch. A batch
+// that does not shrink is stored uncompressed.
+pub const NONE: u8 = 0;
+pub const ZSTD: u8 = 1;
+pub const LZ4: u8 = 2;
+pub const SNAPPY: u8 = 3;
+
+// Compresses an encoded batch and prefixes it with its codec byte.
+pub fn compress(compression: &Compression, batch: &[u8]) -> io::Result<Vec<u8>> {
+    let (codec, compressed): (u8, Vec<u8>) = match compression {
+        Compression::None => return Ok(stored(NONE, batch)),
+        Compression::Zstd(level) => (ZSTD, zstd::bulk::compress(batch, *level)?),
+        Compression::Lz4 => (LZ4, lz4_flex::compress_prepend_size(batch)),
+        Compression::Snappy => (SNAPPY, snap::raw::Encoder::new().compress_vec(batch)?),
+    };
+    if compressed.len() >= batch.len() {
+        return Ok(stored(NONE, batch));
+    }
+    Ok(stored(codec, &compressed))
+}
+
+// Returns the encoded batch held in a frame, or None if the frame names an
+// unknown codec or fails to decompress. Uncompressed batches are slices of
+// `frame` rather than copies of it.
+pub fn decompress(frame: &Bytes) -> Option<Bytes> {
+    let (&codec, data) = frame.split_first()?;
+    let batch: Vec<u8> = match codec {
+        NONE => return Some(frame.slice(1..)),
+        ZSTD => zstd::stream::decode_all(data).ok()?,
+        LZ4 => lz4_flex::decompress_size_prepended(data).ok()?,
+        SNAPPY => snap::raw::Decoder::new().decompress_vec(data).ok()?,
+        _ => return None,
+    };
+    Some(Bytes::from(batch))
+}
+
+fn stored(codec: u8, data: &[u8]) -> Vec<u8> {
+    let mut frame: Vec<u8> = Vec::with_capacity(1 + data.len());
+    frame.push(codec);
+    frame.extend_from_slice(data);
+    frame
+}
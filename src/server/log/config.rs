@@ -0,0 +1,262 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::index::ENT_WIDTH;
+use super::tiered::ObjectStore;
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub segment: SegmentConfig,
+    pub sync: SyncPolicy,
+    pub retention: RetentionConfig,
+    pub compaction: CompactionConfig,
+    pub compression: Compression,
+    // Batches are stored in the clear unless this is set.
+    pub encryption: Option<EncryptionConfig>,
+    // Every segment is kept on local disk unless this is set.
+    pub tiering: Option<TieringConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SegmentConfig {
+    // The log rolls to a new segment once the active store reaches this size.
+    pub max_store_bytes: u64,
+    // The index file is grown to this size up front so it can be memory-mapped.
+    pub max_index_bytes: u64,
+    // Offset assigned to the first record of a brand new log.
+    pub initial_offset: u64,
+}
+
+impl Default for SegmentConfig {
+    fn default() -> Self {
+        Self {
+            max_store_bytes: 1024 * 1024 * 1024,
+            max_index_bytes: 1024 * 1024 * ENT_WIDTH,
+            initial_offset: 0,
+        }
+    }
+}
+
+// Decides when appended records are fsynced to disk. Whatever the policy, an
+// append has handed its record to the operating system by the time it
+// returns; `Log::sync` forces an fsync on demand.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    // Every append returns only once its record has been fsynced.
+    Always,
+    // Fsync once this many records have been appended since the last sync.
+    EveryRecords(u64),
+    // Fsync once this much time has passed since the last sync, including
+    // from a background thread when appends stop arriving.
+    Interval(Duration),
+    // Never fsync explicitly and leave writing back to the operating system.
+    #[default]
+    Os,
+}
+
+// Written as `always`, `os`, `records:<count>` or `interval:<duration>` in
+// configuration, where the duration is in a form such as `500ms` or `1s`.
+impl fmt::Display for SyncPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncPolicy::Always => write!(f, "always"),
+            SyncPolicy::EveryRecords(count) => write!(f, "records:{count}"),
+            SyncPolicy::Interval(interval) => {
+                write!(f, "interval:{}", humantime::format_duration(*interval))
+            }
+            SyncPolicy::Os => write!(f, "os"),
+        }
+    }
+}
+
+impl FromStr for SyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy: Option<SyncPolicy> = match s.split_once(':') {
+            None if s == "always" => Some(SyncPolicy::Always),
+            None if s == "os" => Some(SyncPolicy::Os),
+            Some(("records", count)) => count.parse().ok().map(SyncPolicy::EveryRecords),
+            Some(("interval", interval)) => humantime::parse_duration(interval)
+                .ok()
+                .map(SyncPolicy::Interval),
+            _ => None,
+        };
+        policy.ok_or_else(|| {
+            format!(
+                "invalid sync policy `{s}`, expected always, os, records:<count> or interval:<duration>"
+            )
+        })
+    }
+}
+
+// Codec record batches are compressed with on disk. Reads decompress them
+// transparently whatever the codec, so it can be changed between runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    // Zstandard at the given level, where 3 is a good default.
+    Zstd(i32),
+    Lz4,
+    Snappy,
+}
+
+// Written as `none`, `lz4`, `snappy` or `zstd:<level>` in configuration,
+// where a bare `zstd` stands for level 3.
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Zstd(level) => write!(f, "zstd:{level}"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Snappy => write!(f, "snappy"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let compression: Option<Compression> = match s.split_once(':') {
+            None if s == "none" => Some(Compression::None),
+            None if s == "zstd" => Some(Compression::Zstd(3)),
+            None if s == "lz4" => Some(Compression::Lz4),
+            None if s == "snappy" => Some(Compression::Snappy),
+            Some(("zstd", level)) => level.parse().ok().map(Compression::Zstd),
+            _ => None,
+        };
+        compression.ok_or_else(|| {
+            format!("invalid compression `{s}`, expected none, lz4, snappy or zstd:<level>")
+        })
+    }
+}
+
+// Encryption of record batches at rest. Batches are encrypted with the
+// active key and remember its id, so rotating to a new key only requires
+// adding it to the key file and making it active; batches written under
+// older keys stay readable as long as those keys remain in the file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionConfig {
+    pub key_file: PathBuf,
+    pub active_key_id: u32,
+}
+
+// Limits on how much history the log keeps. Whole segments are deleted,
+// oldest first, once either limit is exceeded; the active segment is always
+// kept.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    // Delete old segments while the log's stores add up to more than this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    // Delete segments whose last record was appended longer ago than this.
+    #[serde(with = "duration_option", skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<Duration>,
+    // How often the background task enforces the limits.
+    #[serde(with = "duration")]
+    pub check_interval: Duration,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: None,
+            max_age: None,
+            check_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+// Key-based compaction of closed segments. Of all the records sharing a key
+// only the latest is kept, at its original offset; records without a key are
+// never compacted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompactionConfig {
+    pub enabled: bool,
+    // How long a tombstone outlives the records it deleted so consumers get
+    // a chance to see it before it is compacted away too.
+    #[serde(with = "duration")]
+    pub tombstone_retention: Duration,
+    // How often the background task compacts the log.
+    #[serde(with = "duration")]
+    pub check_interval: Duration,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tombstone_retention: Duration::from_secs(24 * 60 * 60),
+            check_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+// Tiered storage of closed segments. Closed segments are uploaded to the
+// object store, and their local copies are deleted once they have not been
+// appended to for the hot retention. Reads of offloaded segments fetch them
+// back transparently, one at a time. Offloaded segments are no longer
+// compacted, and retention deletes them from the object store by the age of
+// their last record.
+#[derive(Clone, Debug)]
+pub struct TieringConfig {
+    pub store: Arc<dyn ObjectStore>,
+    pub hot_retention: Duration,
+    // How often the background task uploads and offloads segments.
+    pub check_interval: Duration,
+}
+
+// Durations travel through configuration files in a human readable form such
+// as `90s` or `7days`.
+pub(crate) mod duration {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&humantime::format_duration(*value).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let text: String = String::deserialize(deserializer)?;
+        humantime::parse_duration(&text)
+            .map_err(|err| serde::de::Error::custom(format!("invalid duration `{text}`: {err}")))
+    }
+}
+
+pub(crate) mod duration_option {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => super::duration::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        #[derive(Deserialize)]
+        struct Value(#[serde(with = "super::duration")] Duration);
+
+        let value: Option<Value> = Option::deserialize(deserializer)?;
+        Ok(value.map(|Value(value)| value))
+    }
+}
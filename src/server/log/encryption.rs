@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use super::config::EncryptionConfig;
+use super::error::Error;
+
+// From format version 4 on, every frame starts with the id of the key its
+// batch was encrypted with (big-endian u32). Id 0 means the batch is stored
+// in the clear; otherwise a nonce follows and then the batch sealed with
+// ChaCha20-Poly1305, authenticated together with the key id.
+pub const KEY_ID_WIDTH: usize = 4;
+pub const NONCE_WIDTH: usize = 12;
+pub const PLAINTEXT: u32 = 0;
+
+// The keys batches are encrypted and decrypted with. Old keys stay in the key
+// file after a rotation so batches written under them remain readable.
+#[derive(Default)]
+pub struct Keyring {
+    keys: HashMap<u32, ChaCha20Poly1305>,
+    // Key new batches are encrypted with, if encryption is enabled.
+    active: Option<u32>,
+}
+
+impl Keyring {
+    // Loads the key file named by the config. Each line of the file holds a
+    // key id and a base64 encoded 256-bit key separated by whitespace; blank
+    // lines and lines starting with `#` are skipped.
+    pub fn load(config: Option<&EncryptionConfig>) -> Result<Self, Error> {
+        let Some(config) = config else {
+            return Ok(Self::default());
+        };
+        let contents: String = fs::read_to_string(&config.key_file)?;
+        let mut keys: HashMap<u32, ChaCha20Poly1305> = HashMap::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line: &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key_id, key) = parse_key(line).ok_or_else(|| {
+                let message = format!("{}:{}: invalid key", config.key_file.display(), i + 1);
+                io::Error::new(io::ErrorKind::InvalidData, message)
+            })?;
+            keys.insert(key_id, ChaCha20Poly1305::new(&key));
+        }
+        if !keys.contains_key(&config.active_key_id) {
+            return Err(Error::MissingKey {
+                key_id: config.active_key_id,
+            });
+        }
+        Ok(Self {
+            keys,
+            active: Some(config.active_key_id),
+        })
+    }
+
+    // Encrypts a frame's payload with the active key and prefixes it with
+    // the key id and nonce.
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let Some(key_id) = self.active else {
+            let mut frame: Vec<u8> = Vec::with_capacity(KEY_ID_WIDTH + data.len());
+            frame.extend_from_slice(&PLAINTEXT.to_be_bytes());
+            frame.extend_from_slice(data);
+            return Ok(frame);
+        };
+        let cipher: &ChaCha20Poly1305 = &self.keys[&key_id];
+        let nonce: Nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad: [u8; KEY_ID_WIDTH] = key_id.to_be_bytes();
+        let sealed: Vec<u8> = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: data,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| io::Error::other("failed to encrypt batch"))?;
+
+        let mut frame: Vec<u8> = Vec::with_capacity(KEY_ID_WIDTH + NONCE_WIDTH + sealed.len());
+        frame.extend_from_slice(&aad);
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(&sealed);
+        Ok(frame)
+    }
+
+    // Returns the payload of a frame read from the store at `pos`. Payloads
+    // stored in the clear are slices of `frame` rather than copies of it.
+    pub fn decrypt(&self, frame: &Bytes, pos: u64) -> Result<Bytes, Error> {
+        let key_id: [u8; KEY_ID_WIDTH] = frame
+            .get(..KEY_ID_WIDTH)
+            .and_then(|key_id| key_id.try_into().ok())
+            .ok_or(Error::Corrupt { position: pos })?;
+        let key_id: u32 = u32::from_be_bytes(key_id);
+        if key_id == PLAINTEXT {
+            return Ok(frame.slice(KEY_ID_WIDTH..));
+        }
+
+        let cipher: &ChaCha20Poly1305 =
+            self.keys.get(&key_id).ok_or(Error::MissingKey { key_id })?;
+        let sealed: &[u8] = &frame[KEY_ID_WIDTH..];
+        if sealed.len() < NONCE_WIDTH {
+            return Err(Error::Corrupt { position: pos });
+        }
+        let (nonce, msg) = sealed.split_at(NONCE_WIDTH);
+        let nonce: [u8; NONCE_WIDTH] = nonce.try_into().unwrap();
+        let aad: [u8; KEY_ID_WIDTH] = key_id.to_be_bytes();
+        let data: Vec<u8> = cipher
+            .decrypt(&Nonce::from(nonce), Payload { msg, aad: &aad })
+            .map_err(|_| Error::Decryption {
+                key_id,
+                position: pos,
+            })?;
+        Ok(Bytes::from(data))
+    }
+}
+
+fn parse_key(line: &str) -> Option<(u32, Key)> {
+    let mut fields = line.split_whitespace();
+    let key_id: u32 = fields.next()?.parse().ok().filter(|&id| id != PLAINTEXT)?;
+    let key: [u8; 32] = STANDARD.decode(fields.next()?).ok()?.try_into().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((key_id, Key::from(key)))
+}
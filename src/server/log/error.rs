@@ -0,0 +1,108 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use super::store::{LEGACY_VERSION, VERSION};
+
+#[derive(Debug)]
+pub enum Error {
+    // No record has been appended at this offset.
+    OffsetOutOfRange(u64),
+    // The record at this offset has been deleted; the log now starts at
+    // `lowest_offset`.
+    OffsetTruncated { offset: u64, lowest_offset: u64 },
+    // Compaction removed the record at this offset because a later record
+    // with the same key superseded it.
+    Compacted { offset: u64 },
+    // The record stored at this position failed its checksum or could not be
+    // decoded.
+    Corrupt { position: u64 },
+    // A segment starts before the previous segment ends, so the same offset
+    // would be stored twice.
+    SegmentOverlap { base_offset: u64, next_offset: u64 },
+    // The segment has no room left in its index for another record.
+    SegmentFull { base_offset: u64 },
+    // A batch append was given no records.
+    EmptyBatch,
+    // A batch holds more records than a single segment can index.
+    BatchTooLarge { count: u64, max: u64 },
+    // The log has been closed and no longer accepts appends or reads.
+    Closed,
+    // A store was written in an on-disk format version this build does not
+    // know, most likely by a newer release.
+    UnsupportedVersion { version: u32 },
+    // A batch was encrypted with a key that is not in the key file.
+    MissingKey { key_id: u32 },
+    // The batch stored at this position failed to decrypt, so the key with
+    // this id is not the one it was encrypted with.
+    Decryption { key_id: u32, position: u64 },
+    // Another process has the log in this directory open.
+    Locked { dir: PathBuf },
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OffsetOutOfRange(offset) => write!(f, "offset {offset} is out of range"),
+            Error::OffsetTruncated {
+                offset,
+                lowest_offset,
+            } => write!(
+                f,
+                "offset {offset} has been truncated, the log now starts at offset {lowest_offset}"
+            ),
+            Error::Compacted { offset } => write!(
+                f,
+                "offset {offset} has been compacted away by a later record with the same key"
+            ),
+            Error::Corrupt { position } => write!(f, "corrupt record at position {position}"),
+            Error::SegmentOverlap {
+                base_offset,
+                next_offset,
+            } => write!(
+                f,
+                "segment {base_offset} overlaps the previous segment ending at offset {next_offset}"
+            ),
+            Error::SegmentFull { base_offset } => write!(f, "segment {base_offset} is full"),
+            Error::EmptyBatch => write!(f, "batch has no records"),
+            Error::BatchTooLarge { count, max } => {
+                write!(f, "batch of {count} records exceeds the limit of {max}")
+            }
+            Error::Closed => write!(f, "log is closed"),
+            Error::UnsupportedVersion { version } => write!(
+                f,
+                "unsupported on-disk format version {version}, expected {} to {}",
+                LEGACY_VERSION, VERSION
+            ),
+            Error::MissingKey { key_id } => {
+                write!(f, "encryption key {key_id} is missing from the key file")
+            }
+            Error::Decryption { key_id, position } => write!(
+                f,
+                "batch at position {position} failed to decrypt with key {key_id}"
+            ),
+            Error::Locked { dir } => write!(
+                f,
+                "the log in {} is already open in another process",
+                dir.display()
+            ),
+            Error::Io(err) => write!(f, "i/o error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
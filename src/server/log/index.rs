@@ -0,0 +1,191 @@
+use std::fs::File;
+use std::io;
+
+use memmap2::MmapMut;
+
+use super::config::Config;
+
+// Each entry is a record's offset relative to the index's base offset
+// (big-endian u32) followed by its position in the store (big-endian u64).
+const OFF_WIDTH: u64 = 4;
+const POS_WIDTH: u64 = 8;
+pub const ENT_WIDTH: u64 = OFF_WIDTH + POS_WIDTH;
+
+pub struct Index {
+    file: File,
+    mmap: Option<MmapMut>,
+    size: u64,
+}
+
+impl Index {
+    // `first_frame` is the position of the first frame in the segment's
+    // store, if it holds any, as `parse` takes it.
+    pub fn new(file: File, config: &Config, first_frame: Option<u64>) -> io::Result<Self> {
+        let len: u64 = file.metadata()?.len();
+        file.set_len(len.max(config.segment.max_index_bytes))?;
+        // Safety: the file is owned by this index and only ever resized after
+        // the map has been dropped in `close`.
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        // After a clean close the file is exactly as long as its entries, but
+        // after a crash it is still padded with zeroes, which `parse` stops at.
+        let size: u64 = parse(&mmap[..len as usize], first_frame).len() as u64 * ENT_WIDTH;
+        Ok(Self {
+            file,
+            mmap: Some(mmap),
+            size,
+        })
+    }
+
+    // Returns the relative offset and store position of the entry at `index`.
+    pub fn read(&self, index: u64) -> io::Result<(u32, u64)> {
+        if index >= self.entries() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        Ok(self.entry(index))
+    }
+
+    // Returns the store position of the record at relative offset `off`, if
+    // the index has an entry for it. Entries are dense until compaction
+    // removes some, so the entry at index `off` is tried before searching.
+    pub fn find(&self, off: u32) -> Option<u64> {
+        if (off as u64) < self.entries() {
+            let (found, pos) = self.entry(off as u64);
+            if found == off {
+                return Some(pos);
+            }
+        }
+        let index: u64 = self.seek(off);
+        match self.read(index) {
+            Ok((found, pos)) if found == off => Some(pos),
+            _ => None,
+        }
+    }
+
+    // Returns the index of the first entry at or after relative offset `off`,
+    // which is `entries()` if every entry is before it.
+    pub fn seek(&self, off: u32) -> u64 {
+        let (mut low, mut high) = (0, self.entries());
+        while low < high {
+            let mid: u64 = low + (high - low) / 2;
+            if self.entry(mid).0 < off {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    pub fn last(&self) -> Option<(u32, u64)> {
+        match self.entries() {
+            0 => None,
+            entries => Some(self.entry(entries - 1)),
+        }
+    }
+
+    pub fn write(&mut self, off: u32, pos: u64) -> io::Result<()> {
+        if self.is_full() {
+            return Err(io::Error::new(io::ErrorKind::StorageFull, "index is full"));
+        }
+        let start = self.size as usize;
+        let mmap = self.mmap.as_mut().expect("index is closed");
+        mmap[start..start + OFF_WIDTH as usize].copy_from_slice(&off.to_be_bytes());
+        mmap[start + OFF_WIDTH as usize..start + ENT_WIDTH as usize]
+            .copy_from_slice(&pos.to_be_bytes());
+        self.size += ENT_WIDTH;
+        Ok(())
+    }
+
+    // Drops every entry from `index` onwards. They are zeroed so that a
+    // crash cannot bring them back once later entries are written in front
+    // of them.
+    pub fn truncate(&mut self, index: u64) {
+        let size: u64 = self.size.min(index * ENT_WIDTH);
+        if let Some(mmap) = self.mmap.as_mut() {
+            mmap[size as usize..self.size as usize].fill(0);
+        }
+        self.size = size;
+    }
+
+    // The entries written so far, laid out as in the file.
+    pub fn bytes(&self) -> &[u8] {
+        self.mmap
+            .as_ref()
+            .map_or(&[], |mmap| &mmap[..self.size as usize])
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.size / ENT_WIDTH
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.free_entries() == 0
+    }
+
+    // Number of entries that can still be written before the index is full.
+    pub fn free_entries(&self) -> u64 {
+        let capacity = self.mmap.as_ref().map_or(0, |mmap| mmap.len() as u64);
+        capacity.saturating_sub(self.size) / ENT_WIDTH
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        match &self.mmap {
+            Some(mmap) => mmap.flush(),
+            None => Ok(()),
+        }
+    }
+
+    // Flushes the map and shrinks the file back to the entries actually
+    // written, so the next open sees exactly where the index ends.
+    pub fn close(&mut self) -> io::Result<()> {
+        if let Some(mmap) = self.mmap.take() {
+            mmap.flush()?;
+            drop(mmap);
+            self.file.set_len(self.size)?;
+            self.file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn entry(&self, index: u64) -> (u32, u64) {
+        let start = (index * ENT_WIDTH) as usize;
+        let mmap = self.mmap.as_ref().expect("index is closed");
+        let off = &mmap[start..start + OFF_WIDTH as usize];
+        let pos = &mmap[start + OFF_WIDTH as usize..start + ENT_WIDTH as usize];
+        (
+            u32::from_be_bytes(off.try_into().unwrap()),
+            u64::from_be_bytes(pos.try_into().unwrap()),
+        )
+    }
+}
+
+// Decodes the entries at the start of an index file's bytes. Offsets only
+// ever increase, so the entries end where that stops being true, which is
+// where the zero padding of an index that was not closed cleanly begins.
+// Padding right at the start reads as an entry for relative offset 0 at
+// position 0, which is only a real entry if `first_frame`, the position of
+// the store's first frame, is 0 too, as in stores written before they had a
+// header.
+pub fn parse(data: &[u8], first_frame: Option<u64>) -> Vec<(u32, u64)> {
+    let mut entries: Vec<(u32, u64)> = Vec::new();
+    for entry in data.chunks_exact(ENT_WIDTH as usize) {
+        let off = u32::from_be_bytes(entry[..OFF_WIDTH as usize].try_into().unwrap());
+        let pos = u64::from_be_bytes(entry[OFF_WIDTH as usize..].try_into().unwrap());
+        let padding: bool = match entries.last() {
+            Some(&(last, _)) => off <= last,
+            None => (off, pos) == (0, 0) && first_frame != Some(0),
+        };
+        if padding {
+            break;
+        }
+        entries.push((off, pos));
+    }
+    entries
+}
+
+impl Drop for Index {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
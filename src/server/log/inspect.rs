@@ -0,0 +1,413 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use super::config::Config;
+use super::encryption::Keyring;
+use super::error::Error;
+use super::segment::{self, Segment};
+use super::store::{Store, FRAME_WIDTH, HEADER_WIDTH, LEN_WIDTH, VERSION};
+use super::{base_offsets, index, lock, timeindex, Record};
+
+// Offline tools for looking into a log directory when a node misbehaves.
+// Inspecting and dumping never write to the log's files, unlike opening the
+// log, which recovers from a crash by cutting torn data off. Repairing takes
+// the log's lock, so it refuses to touch a log that is open elsewhere.
+
+// What a segment's files hold and anything found wrong with them.
+#[derive(Clone, Debug, Default)]
+pub struct SegmentReport {
+    pub base_offset: u64,
+    // Format version of the store.
+    pub version: u32,
+    pub store_bytes: u64,
+    pub batches: u64,
+    pub records: u64,
+    pub first_offset: Option<u64>,
+    pub last_offset: Option<u64>,
+    // Position of the first frame that fails its checksum, where a repair
+    // cuts the store.
+    pub torn_at: Option<u64>,
+    // Readable records found past the first frame that fails its checksum,
+    // which a repair would throw away: how many, and the last one's offset.
+    pub stranded_records: u64,
+    pub stranded_last_offset: Option<u64>,
+    // Why the segment's indexes cannot be rebuilt from its store at all, if
+    // they cannot: a format version this build does not know, or a batch
+    // whose key is missing from the key file.
+    pub unrepairable: Option<String>,
+    pub problems: Vec<String>,
+}
+
+// What `repair` did to a segment, or would have done.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Repair {
+    pub base_offset: u64,
+    // Size of the store before and after the repair, which differ if the
+    // store was cut at its first unreadable frame.
+    pub store_bytes: (u64, u64),
+    // The first and last offset cut off along with the store, if any are
+    // known: those of the readable records past the unreadable frame.
+    pub dropped_offsets: Option<(u64, u64)>,
+    // Whether the store was missing, or too short to hold its header, so the
+    // repair puts a new, empty one in its place.
+    pub recreated: bool,
+    pub outcome: Outcome,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    // The indexes were rebuilt, with the store cut or recreated as the sizes
+    // show.
+    Applied,
+    // Left as it is because the repair would have thrown readable records
+    // away, or recreated a missing store, and was not forced. The sizes are
+    // those the store would have been cut to.
+    NeedsForce,
+    // Left as it is because its store cannot be read, for this reason.
+    Unrepairable(String),
+}
+
+// Reads every segment in `dir`, checking each frame's checksum, decoding
+// each batch with the keys named by `config`, and making sure the index and
+// time index agree with the store.
+pub fn inspect(dir: &Path, config: &Config) -> Result<Vec<SegmentReport>, Error> {
+    let keys: Keyring = Keyring::load(config.encryption.as_ref())?;
+    base_offsets(dir)?
+        .into_iter()
+        .map(|base_offset| inspect_segment(dir, base_offset, &keys))
+        .collect()
+}
+
+// Hands every record from `from` to `to`, inclusive, to `f` in offset
+// order. Batches that cannot be read are skipped; `inspect` reports them.
+pub fn dump(
+    dir: &Path,
+    config: &Config,
+    from: u64,
+    to: u64,
+    mut f: impl FnMut(Record) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let keys: Keyring = Keyring::load(config.encryption.as_ref())?;
+    let base_offsets: Vec<u64> = base_offsets(dir)?;
+    for (i, &base_offset) in base_offsets.iter().enumerate() {
+        let next_base_offset: u64 = base_offsets.get(i + 1).copied().unwrap_or(u64::MAX);
+        let path: PathBuf = store_path(dir, base_offset);
+        if next_base_offset <= from || base_offset > to || !path.exists() {
+            continue;
+        }
+        scan(&path, &keys, |_, batch| {
+            for record in batch.into_iter().flatten() {
+                if (from..=to).contains(&record.offset) {
+                    f(record)?;
+                }
+            }
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+// Rebuilds the index and time index of every segment `inspect` finds a
+// problem with from its store, the way opening the log does after a crash.
+// A store is cut at its first frame that fails its checksum, since the log
+// cannot find its way past it. Segments whose cut would throw readable
+// records away, or whose store is missing, are left as they are unless
+// `force` is set, since an empty store in place of a missing one would make
+// its offsets read as compacted.
+pub fn repair(dir: &Path, config: &Config, force: bool) -> Result<Vec<Repair>, Error> {
+    let _lock: File = lock(dir)?;
+    let keys: Arc<Keyring> = Arc::new(Keyring::load(config.encryption.as_ref())?);
+    let mut repairs: Vec<Repair> = Vec::new();
+    for report in inspect(dir, config)? {
+        if report.problems.is_empty() {
+            continue;
+        }
+        let base_offset: u64 = report.base_offset;
+        let dropped_offsets: Option<(u64, u64)> = report.stranded_last_offset.map(|last| {
+            let first: u64 = report.last_offset.map_or(base_offset, |kept| kept + 1);
+            (first, last)
+        });
+        let missing: bool = !store_path(dir, base_offset).exists();
+        let mut repair = Repair {
+            base_offset,
+            store_bytes: (report.store_bytes, report.store_bytes),
+            dropped_offsets,
+            recreated: report.store_bytes < HEADER_WIDTH,
+            outcome: Outcome::Applied,
+        };
+        if let Some(reason) = report.unrepairable {
+            repair.outcome = Outcome::Unrepairable(reason);
+        } else if (missing || dropped_offsets.is_some()) && !force {
+            repair.store_bytes.1 = match report.torn_at {
+                Some(torn_at) => torn_at,
+                None => HEADER_WIDTH,
+            };
+            repair.outcome = Outcome::NeedsForce;
+        } else {
+            Segment::rebuild(dir, base_offset, config, &keys)?;
+            repair.store_bytes.1 = fs::metadata(store_path(dir, base_offset))?.len();
+        }
+        repairs.push(repair);
+    }
+    Ok(repairs)
+}
+
+fn inspect_segment(dir: &Path, base_offset: u64, keys: &Keyring) -> Result<SegmentReport, Error> {
+    let mut report = SegmentReport {
+        base_offset,
+        ..Default::default()
+    };
+    // The entries the index and time index should hold, as long as the whole
+    // store can be decoded.
+    let mut index: Option<Vec<(u32, u64)>> = Some(Vec::new());
+    let mut time_index: Vec<(u64, u32)> = Vec::new();
+
+    let path: PathBuf = store_path(dir, base_offset);
+    if !path.exists() {
+        report.problems.push("store file is missing".to_string());
+        return Ok(report);
+    }
+    let scanned = match scan(&path, keys, |pos, batch| {
+        report.batches += 1;
+        let records: Vec<Record> = match batch {
+            Ok(records) => records,
+            Err(err) => {
+                let problem: String = format!("batch at position {pos}: {err}");
+                // Rebuilding the indexes stops at a corrupt batch, but fails
+                // on one it has no key for.
+                if !matches!(err, Error::Corrupt { .. }) {
+                    report.unrepairable.get_or_insert_with(|| problem.clone());
+                }
+                report.problems.push(problem);
+                index = None;
+                return Ok(());
+            }
+        };
+        for record in &records {
+            if record.offset < base_offset
+                || report.last_offset.is_some_and(|last| record.offset <= last)
+            {
+                report.problems.push(format!(
+                    "batch at position {pos} holds offset {} out of order",
+                    record.offset
+                ));
+                index = None;
+                return Ok(());
+            }
+            report.first_offset.get_or_insert(record.offset);
+            report.last_offset = Some(record.offset);
+            report.records += 1;
+            if let Some(index) = &mut index {
+                index.push(((record.offset - base_offset) as u32, pos));
+            }
+        }
+        if let Some(first) = records.first() {
+            let off: u32 = (first.offset - base_offset) as u32;
+            if time_index
+                .last()
+                .is_none_or(|&(last, _)| first.timestamp > last)
+            {
+                time_index.push((first.timestamp, off));
+            }
+        }
+        Ok(())
+    }) {
+        Ok(scanned) => scanned,
+        Err(err @ Error::UnsupportedVersion { .. }) => {
+            report.store_bytes = fs::metadata(&path)?.len();
+            report.problems.push(err.to_string());
+            report.unrepairable = Some(err.to_string());
+            return Ok(report);
+        }
+        Err(err) => return Err(err),
+    };
+    (report.version, report.store_bytes) = (scanned.version, scanned.size);
+    report.torn_at = scanned.torn_at;
+    if let Some(position) = scanned.torn_at {
+        // The indexes cannot be checked against records that cannot be read.
+        index = None;
+        report.problems.push(format!(
+            "store is unreadable from position {position} on, where a frame fails its checksum"
+        ));
+        stranded(&path, position, scanned.version, keys, &mut report)?;
+        if let Some(last) = report.stranded_last_offset {
+            report.problems.push(format!(
+                "{} readable records up to offset {last} follow the unreadable frame",
+                report.stranded_records
+            ));
+        }
+    }
+
+    if let Some(index) = index {
+        check(
+            &mut report.problems,
+            "index",
+            &dir.join(format!("{base_offset}.index")),
+            index::ENT_WIDTH,
+            |data| index::parse(data, scanned.first_frame),
+            &index,
+        )?;
+        check(
+            &mut report.problems,
+            "time index",
+            &dir.join(format!("{base_offset}.timeindex")),
+            timeindex::TIME_ENT_WIDTH,
+            timeindex::parse,
+            &time_index,
+        )?;
+    }
+    Ok(report)
+}
+
+// The outcome of walking a store with `scan`.
+struct Scanned {
+    version: u32,
+    size: u64,
+    // Position of the store's first frame, if it holds any.
+    first_frame: Option<u64>,
+    // Position of the first frame that fails its checksum, if any.
+    torn_at: Option<u64>,
+}
+
+// Walks the batches of the store at `path` without modifying it, handing
+// each to `f` along with its position. A batch that fails to decode is
+// handed over as an error and the walk carries on, but a frame that fails
+// its checksum ends it, since its length cannot be trusted.
+fn scan(
+    path: &Path,
+    keys: &Keyring,
+    mut f: impl FnMut(u64, Result<Vec<Record>, Error>) -> Result<(), Error>,
+) -> Result<Scanned, Error> {
+    let file = File::open(path)?;
+    let size: u64 = file.metadata()?.len();
+    if size < HEADER_WIDTH {
+        // Opening the log writes a fresh header over a torn one.
+        return Ok(Scanned {
+            version: VERSION,
+            size,
+            first_frame: None,
+            torn_at: (size > 0).then_some(0),
+        });
+    }
+    let store = Store::new(file)?;
+    let mut pos: u64 = store.start();
+    let mut torn_at: Option<u64> = None;
+    while pos < size {
+        let data = match store.read(pos) {
+            Ok(data) => data,
+            Err(Error::Corrupt { .. }) => {
+                torn_at = Some(pos);
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+        f(pos, segment::decode(store.version(), keys, &data, pos))?;
+        pos += FRAME_WIDTH + data.len() as u64;
+    }
+    Ok(Scanned {
+        version: store.version(),
+        size,
+        first_frame: (size > store.start()).then_some(store.start()),
+        torn_at,
+    })
+}
+
+// Looks past the unreadable frame at `torn_at` for frames that still pass
+// their checksum and decode to records after those already read, counting
+// them into the report. The unreadable frame's length cannot be trusted, so
+// the search steps through the store a byte at a time until a frame fits.
+fn stranded(
+    path: &Path,
+    torn_at: u64,
+    version: u32,
+    keys: &Keyring,
+    report: &mut SegmentReport,
+) -> Result<(), Error> {
+    let data = Bytes::from(fs::read(path)?);
+    let mut last: Option<u64> = report.last_offset;
+    let mut pos: usize = torn_at as usize + 1;
+    while pos + FRAME_WIDTH as usize <= data.len() {
+        let header: &[u8] = &data[pos..pos + FRAME_WIDTH as usize];
+        let len = u64::from_be_bytes(header[..LEN_WIDTH as usize].try_into().unwrap());
+        let crc = u32::from_be_bytes(header[LEN_WIDTH as usize..].try_into().unwrap());
+        let start: usize = pos + FRAME_WIDTH as usize;
+        let records: Option<Vec<Record>> = (len <= (data.len() - start) as u64)
+            .then(|| data.slice(start..start + len as usize))
+            .filter(|frame| crc32fast::hash(frame) == crc)
+            .and_then(|frame| segment::decode(version, keys, &frame, pos as u64).ok())
+            .filter(|records| {
+                records.first().is_some_and(|first| {
+                    first.offset >= report.base_offset
+                        && last.is_none_or(|last| first.offset > last)
+                })
+            });
+        let Some(records) = records else {
+            pos += 1;
+            continue;
+        };
+        report.stranded_records += records.len() as u64;
+        last = records.last().map(|record| record.offset);
+        report.stranded_last_offset = last;
+        pos = start + len as usize;
+    }
+    Ok(())
+}
+
+// Compares the entries of an index file with those its store calls for.
+fn check<T: PartialEq + std::fmt::Debug>(
+    problems: &mut Vec<String>,
+    name: &str,
+    path: &Path,
+    width: u64,
+    parse: impl Fn(&[u8]) -> Vec<T>,
+    expected: &[T],
+) -> Result<(), Error> {
+    let data: Vec<u8> = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            problems.push(format!("{name} file is missing"));
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let entries: Vec<T> = parse(&data);
+    let mismatch: Option<usize> = entries
+        .iter()
+        .zip(expected)
+        .position(|(entry, expected)| entry != expected);
+    if let Some(i) = mismatch {
+        problems.push(format!(
+            "{name} entry {i} is {:?} but the store calls for {:?}",
+            entries[i], expected[i]
+        ));
+    } else if entries.len() < expected.len() {
+        problems.push(format!(
+            "{name} is missing the last {} of {} entries",
+            expected.len() - entries.len(),
+            expected.len()
+        ));
+    } else if entries.len() > expected.len() {
+        problems.push(format!(
+            "{name} has {} entries past the end of the store",
+            entries.len() - expected.len()
+        ));
+    }
+    // Whatever follows the entries should be the zero padding of an index
+    // that was not closed cleanly.
+    let end: usize = entries.len() * width as usize;
+    if data[end..].iter().any(|&byte| byte != 0) {
+        problems.push(format!(
+            "{name} has unreadable bytes after entry {}",
+            entries.len()
+        ));
+    }
+    Ok(())
+}
+
+fn store_path(dir: &Path, base_offset: u64) -> PathBuf {
+    dir.join(format!("{base_offset}.store"))
+}
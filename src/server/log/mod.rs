@@ -0,0 +1,1145 @@
+pub mod batch;
+mod cache;
+pub mod compression;
+pub mod config;
+pub mod encryption;
+pub mod error;
+pub mod index;
+pub mod inspect;
+pub mod segment;
+mod shared;
+pub mod snapshot;
+pub mod store;
+pub mod tiered;
+pub mod timeindex;
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use cache::{Fetched, SegmentCache};
+pub use config::{
+    CompactionConfig, Compression, Config, EncryptionConfig, RetentionConfig, SegmentConfig,
+    SyncPolicy, TieringConfig,
+};
+use encryption::Keyring;
+pub use error::Error;
+use index::ENT_WIDTH;
+use segment::{Batches, Compacted, Segment, Upload};
+pub use shared::SharedLog;
+pub use snapshot::{Manifest, Snapshot};
+use tiered::ObjectStore;
+
+// Whoever opens the log holds an exclusive lock on this file in its
+// directory until the log is dropped, so no two processes ever open the same
+// log at once.
+const LOCK_FILE: &str = "lock";
+
+// Offloaded segments are downloaded into this subdirectory of the log to be
+// read.
+const FETCHED_DIR: &str = "fetched";
+
+// Closed segments are staged in this subdirectory of the log while they are
+// uploaded.
+const UPLOAD_DIR: &str = "upload";
+
+// The stats of every segment before the active one, saved whenever the log
+// rolls so they need not be counted again on the next open.
+const STATS_FILE: &str = "stats.json";
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Record {
+    // A keyed record without a value is a tombstone: once the log is
+    // compacted it deletes every earlier record with the same key.
+    #[serde(with = "base64_option")]
+    pub value: Option<Bytes>,
+    #[serde(default)]
+    pub offset: u64,
+    // When the log accepted the record, in milliseconds since the Unix epoch.
+    // Assigned on append and never earlier than any record before it.
+    #[serde(default)]
+    pub timestamp: u64,
+    // When the event the record describes happened, as told by its producer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_time: Option<u64>,
+    // Routing key, used to partition and compact records downstream.
+    #[serde(
+        default,
+        with = "base64_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub key: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<Header>,
+}
+
+// A piece of metadata attached to a record. Keys need not be unique.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub key: String,
+    #[serde(with = "base64_value")]
+    pub value: Bytes,
+}
+
+// Sizes of the batches appended to the log, as encoded and after
+// compression, framing and encryption aside. They carry over across runs but
+// not across restores, which count what the restored segments hold.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    pub raw_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+// What is saved in the stats file: the stats of every segment below
+// `active`, the base offset of the active segment when they were saved.
+#[derive(Serialize, Deserialize)]
+struct SavedStats {
+    active: u64,
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+// The log is an ordered list of segments. Only the last one, the active
+// segment, is ever appended to. With tiering, the oldest segments may only
+// be held by the object store.
+pub struct Log {
+    dir: PathBuf,
+    // Holds the lock on the directory, released when the log is dropped.
+    _lock: File,
+    config: Config,
+    keys: Arc<Keyring>,
+    segments: Vec<Segment>,
+    // Base offsets of the segments only the object store holds, all older
+    // than the local ones.
+    offloaded: Vec<u64>,
+    // Base offsets of the local segments the object store holds a copy of.
+    uploaded: HashSet<u64>,
+    // Offloaded segments fetched back for reads, when tiering is configured.
+    cache: Option<Arc<SegmentCache>>,
+    // Bumped whenever compaction rewrites segments, so an upload that ran
+    // meanwhile is known to have copied an outdated segment.
+    rewrites: u64,
+    // Records appended since the last fsync, and when that fsync happened.
+    unsynced: u64,
+    last_sync: Instant,
+    // Fsyncs of the active segment since the log was opened.
+    syncs: u64,
+    // Commits left to fail on purpose, set by tests of the rollback.
+    #[cfg(feature = "fault-injection")]
+    commit_failures: u32,
+    // The latest append timestamp handed out, which later appends never go
+    // below even if the clock steps back.
+    last_timestamp: u64,
+    stats: Stats,
+}
+
+impl Log {
+    // Opens the log stored in `dir`, picking up any segments left behind by a
+    // previous run so appends resume at the next offset. An empty directory
+    // starts a new log at the configured initial offset.
+    pub fn open(dir: impl AsRef<Path>, config: Config) -> Result<Self, Error> {
+        let dir: PathBuf = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let lock: File = lock(&dir)?;
+
+        let base_offsets: Vec<u64> = base_offsets(&dir)?;
+        let keys: Arc<Keyring> = Arc::new(Keyring::load(config.encryption.as_ref())?);
+        let mut log = Self {
+            dir,
+            _lock: lock,
+            config,
+            keys,
+            segments: Vec::new(),
+            offloaded: Vec::new(),
+            uploaded: HashSet::new(),
+            cache: None,
+            rewrites: 0,
+            unsynced: 0,
+            last_sync: Instant::now(),
+            syncs: 0,
+            #[cfg(feature = "fault-injection")]
+            commit_failures: 0,
+            last_timestamp: 0,
+            stats: Stats::default(),
+        };
+        for base_offset in base_offsets {
+            if let Some(previous) = log.segments.last() {
+                if base_offset < previous.next_offset() {
+                    return Err(Error::SegmentOverlap {
+                        base_offset,
+                        next_offset: previous.next_offset(),
+                    });
+                }
+            }
+            if let Some(previous) = log.segments.last_mut() {
+                previous.seal()?;
+            }
+            log.new_segment(base_offset)?;
+        }
+        if log.segments.is_empty() {
+            log.new_segment(log.config.segment.initial_offset)?;
+        }
+        log.last_timestamp = log
+            .segments
+            .iter()
+            .rev()
+            .find_map(Segment::max_timestamp)
+            .unwrap_or(0);
+        log.stats = log.load_stats()?;
+        // An active segment left in an older format could neither compress
+        // nor encrypt appends, so the log moves on to one in the current
+        // format.
+        if log.active_segment().version() < store::VERSION {
+            let active: &Segment = log.active_segment();
+            if active.next_offset() > active.base_offset() {
+                log.roll()?;
+            } else {
+                let base_offset: u64 = active.base_offset();
+                log.segments.pop().expect("log has no segments").remove()?;
+                log.new_segment(base_offset)?;
+            }
+        }
+
+        if let Some(objects) = log.object_store() {
+            // Leftovers of reads, uploads and snapshots from a previous run.
+            for leftover in [FETCHED_DIR, UPLOAD_DIR, snapshot::SNAPSHOT_DIR] {
+                let leftover: PathBuf = log.dir.join(leftover);
+                if leftover.exists() {
+                    fs::remove_dir_all(leftover)?;
+                }
+            }
+            let first_local: u64 = log.segments[0].base_offset();
+            for base_offset in segment::remote_base_offsets(objects.as_ref())? {
+                if base_offset < first_local {
+                    log.offloaded.push(base_offset);
+                } else if log.segments[..log.segments.len() - 1]
+                    .iter()
+                    .any(|segment| segment.base_offset() == base_offset)
+                {
+                    log.uploaded.insert(base_offset);
+                }
+            }
+            log.cache = Some(Arc::new(SegmentCache::new(
+                log.dir.join(FETCHED_DIR),
+                objects,
+                log.config.clone(),
+                log.keys.clone(),
+            )));
+        }
+        Ok(log)
+    }
+
+    // Rebuilds a log in `dir` from a snapshot written by `Snapshot::write_to`
+    // and opens it. The directory must not exist yet or be empty, and is
+    // left untouched if the snapshot turns out to be invalid.
+    pub fn restore(
+        reader: impl Read,
+        dir: impl AsRef<Path>,
+        config: Config,
+    ) -> Result<Self, Error> {
+        snapshot::restore(reader, dir.as_ref(), &config)?;
+        Self::open(dir, config)
+    }
+
+    // Appends the record and returns its offset once the record has reached
+    // the durability point chosen by the sync policy.
+    pub fn append(&mut self, record: Record) -> Result<u64, Error> {
+        let (offset, _) = self.append_batch(vec![record])?;
+        Ok(offset)
+    }
+
+    // Atomically appends the records under a contiguous range of offsets and
+    // returns the first and last of them. Either every record is persisted or
+    // none is: if the commit fails, the records are taken back out of the
+    // log and their offsets are handed out again.
+    pub fn append_batch(&mut self, records: Vec<Record>) -> Result<(u64, u64), Error> {
+        let mark: Mark = self.mark();
+        let offsets: (u64, u64) = self.write(records)?;
+        if let Err(err) = self.commit() {
+            self.rollback(mark)?;
+            return Err(err);
+        }
+        Ok(offsets)
+    }
+
+    // Writes the records to the active segment without making them durable;
+    // `commit` does that for everything written since the last commit. A
+    // batch never spans segments, so the log rolls first if the active
+    // segment cannot index the whole batch.
+    fn write(&mut self, records: Vec<Record>) -> Result<(u64, u64), Error> {
+        let count: u64 = records.len() as u64;
+        let max: u64 = self.config.segment.max_index_bytes / ENT_WIDTH;
+        if count == 0 {
+            return Err(Error::EmptyBatch);
+        }
+        if count > max {
+            return Err(Error::BatchTooLarge { count, max });
+        }
+
+        if self.active_segment().is_maxed() || !self.active_segment().has_room_for(count) {
+            self.roll()?;
+        }
+        let timestamp: u64 = now_millis().max(self.last_timestamp);
+        let active = self.segments.last_mut().expect("log has no segments");
+        let offsets: (u64, u64) = active.append(records, timestamp, &mut self.stats)?;
+        self.last_timestamp = timestamp;
+        self.unsynced += count;
+        Ok(offsets)
+    }
+
+    // Brings the records written so far up to the durability point chosen by
+    // the sync policy, with a single fsync however many records there are.
+    fn commit(&mut self) -> Result<(), Error> {
+        #[cfg(feature = "fault-injection")]
+        if self.commit_failures > 0 {
+            self.commit_failures -= 1;
+            self.active_segment().flush()?;
+            return Err(io::Error::other("injected commit failure").into());
+        }
+        let sync_due: bool = match self.config.sync {
+            SyncPolicy::Always => true,
+            SyncPolicy::EveryRecords(records) => self.unsynced >= records,
+            SyncPolicy::Interval(interval) => self.last_sync.elapsed() >= interval,
+            SyncPolicy::Os => false,
+        };
+        if sync_due {
+            self.sync()
+        } else {
+            self.active_segment().flush()
+        }
+    }
+
+    // The state of the log before a write, for `rollback`.
+    fn mark(&self) -> Mark {
+        Mark {
+            segments: self.segments.len(),
+            active: self.active_segment().mark(),
+            last_timestamp: self.last_timestamp,
+            unsynced: self.unsynced,
+            stats: self.stats,
+        }
+    }
+
+    // Takes back everything written since `mark`, deleting any segment
+    // rolled to since and reopening the one that was active then.
+    fn rollback(&mut self, mark: Mark) -> Result<(), Error> {
+        if self.segments.len() > mark.segments {
+            // The saved stats belong to a segment that is about to go.
+            match fs::remove_file(self.dir.join(STATS_FILE)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        while self.segments.len() > mark.segments {
+            self.segments.pop().expect("log has no segments").remove()?;
+        }
+        self.segments
+            .last_mut()
+            .expect("log has no segments")
+            .rollback(mark.active)?;
+        self.last_timestamp = mark.last_timestamp;
+        self.unsynced = mark.unsynced;
+        self.stats = mark.stats;
+        Ok(())
+    }
+
+    // Makes the next `count` commits fail once their records have reached
+    // the store file, as if the disk had refused a flush or fsync. Only
+    // built with the `fault-injection` feature, which the tests turn on.
+    #[cfg(feature = "fault-injection")]
+    pub fn inject_commit_failures(&mut self, count: u32) {
+        self.commit_failures = count;
+    }
+
+    // Fsyncs every record appended so far, regardless of the sync policy.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.active_segment().sync()?;
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        self.syncs += 1;
+        Ok(())
+    }
+
+    // How many times the log has been fsynced since it was opened, whether
+    // by the sync policy or on demand.
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+
+    // Records appended since the last fsync.
+    pub fn unsynced(&self) -> u64 {
+        self.unsynced
+    }
+
+    pub fn read(&self, offset: u64) -> Result<Record, Error> {
+        match self.read_local(offset)? {
+            Progress::Done(record) => Ok(record),
+            Progress::Fetch(fetch) => fetch.segment()?.read(offset),
+        }
+    }
+
+    // Reads the record at `offset` if it is held locally, or says which
+    // offloaded segment has to be fetched for it.
+    fn read_local(&self, offset: u64) -> Result<Progress<Record>, Error> {
+        match self.segment_index(offset)? {
+            Tier::Local(index) => Ok(Progress::Done(self.segments[index].read(offset)?)),
+            Tier::Offloaded(index) => Ok(Progress::Fetch(self.fetch(index))),
+        }
+    }
+
+    // Reads consecutive records starting at `start`, stopping after
+    // `max_records` records or once their values add up to `max_bytes`, and
+    // returns them with the offset to continue from. The first record is
+    // returned even if it alone exceeds `max_bytes` so consumers always make
+    // progress. Reading from the end of the log returns no records.
+    pub fn read_range(
+        &self,
+        start: u64,
+        max_records: usize,
+        max_bytes: u64,
+    ) -> Result<(Vec<Record>, u64), Error> {
+        let mut page = Page::new(start, max_records, max_bytes);
+        loop {
+            let fetch: Fetch = match self.fill_local(&mut page)? {
+                Progress::Done(next) => return Ok((page.records, next)),
+                Progress::Fetch(fetch) => fetch,
+            };
+            if let Some(next) = page.fill_offloaded(fetch)? {
+                return Ok((page.records, next));
+            }
+        }
+    }
+
+    // Fills the page from the local segments, returning the offset to
+    // continue from, or stops at the first offloaded segment it needs.
+    fn fill_local(&self, page: &mut Page) -> Result<Progress<u64>, Error> {
+        let end: u64 = self.active_segment().next_offset();
+        if page.start == end {
+            return Ok(Progress::Done(end));
+        }
+        let first: usize = match self.segment_index(page.start)? {
+            Tier::Local(index) => index,
+            Tier::Offloaded(index) => return Ok(Progress::Fetch(self.fetch(index))),
+        };
+        for segment in &self.segments[first..] {
+            if let Some(next) = page.fill(segment)? {
+                return Ok(Progress::Done(next));
+            }
+        }
+        Ok(Progress::Done(end))
+    }
+
+    // Returns the offset of the first record appended at or after
+    // `timestamp`, in milliseconds since the Unix epoch. If every record is
+    // older, that is the offset the next append will get. Offloaded segments
+    // are only probed if the record may be among theirs.
+    pub fn offset_for_time(&self, timestamp: u64) -> Result<u64, Error> {
+        self.time_search(timestamp).run()
+    }
+
+    // Captures what `offset_for_time` needs, so the object store can be
+    // probed without holding the log.
+    fn time_search(&self, timestamp: u64) -> TimeSearch {
+        let local: u64 = self
+            .segments
+            .iter()
+            .find_map(|segment| segment.offset_for_time(timestamp))
+            .unwrap_or_else(|| self.active_segment().next_offset());
+        let local_only: bool = self.offloaded.is_empty()
+            || self.segments[0]
+                .min_timestamp()
+                .is_some_and(|min| timestamp > min);
+        TimeSearch {
+            objects: self.object_store(),
+            probes: self.dir.join(FETCHED_DIR),
+            offloaded: if local_only {
+                Vec::new()
+            } else {
+                self.offloaded.clone()
+            },
+            timestamp,
+            local,
+        }
+    }
+
+    // Takes a consistent snapshot of every record up to and including
+    // `upto`, or of the whole log if `upto` is past its end. Batches are
+    // never split, so the snapshot may run on to the end of the batch
+    // holding `upto`. Only file handles and lengths are captured here;
+    // the records are copied by `Snapshot::write_to`, which leaves the log
+    // free to take appends meanwhile.
+    pub fn snapshot(&self, upto: u64) -> Result<Snapshot, Error> {
+        let lowest_offset: u64 = self.lowest_offset();
+        if upto < lowest_offset {
+            return Err(Error::OffsetTruncated {
+                offset: upto,
+                lowest_offset,
+            });
+        }
+        let entries = self
+            .segments
+            .iter()
+            .filter(|segment| segment.base_offset() <= upto)
+            .map(|segment| segment.snapshot(upto))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let offloaded: Vec<u64> = self
+            .offloaded
+            .iter()
+            .copied()
+            .filter(|&base_offset| base_offset <= upto)
+            .collect();
+        Ok(Snapshot {
+            dir: self.dir.clone(),
+            config: self.config.clone(),
+            keys: self.keys.clone(),
+            upto,
+            lowest_offset,
+            offloaded,
+            entries,
+        })
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // The oldest offset that can still be read.
+    pub fn lowest_offset(&self) -> u64 {
+        match self.offloaded.first() {
+            Some(&base_offset) => base_offset,
+            None => self.segments[0].base_offset(),
+        }
+    }
+
+    // The offset of the most recently appended record, if there is one.
+    pub fn highest_offset(&self) -> Option<u64> {
+        let next_offset: u64 = self.active_segment().next_offset();
+        (next_offset > self.lowest_offset()).then(|| next_offset - 1)
+    }
+
+    // Deletes the oldest segments until the log is back within the limits set
+    // by its retention config. The active segment is never deleted, so the
+    // log always keeps at least its most recent records. Only local segments
+    // count towards the size limit, but offloaded segments are older than
+    // any of them and go first.
+    pub fn enforce_retention(&mut self) -> Result<(), Error> {
+        let retention = self.config.retention.clone();
+        let mut total: u64 = self.segments.iter().map(Segment::size).sum();
+
+        if let (Some(objects), Some(max_age)) = (self.object_store(), retention.max_age) {
+            let horizon: u64 = now_millis().saturating_sub(max_age.as_millis() as u64);
+            let probes: PathBuf = self.dir.join(FETCHED_DIR);
+            while let Some(&base_offset) = self.offloaded.first() {
+                let last = segment::remote_max_timestamp(objects.as_ref(), &probes, base_offset)?;
+                if last.is_some_and(|last| last >= horizon) {
+                    break;
+                }
+                self.remove_oldest()?;
+            }
+        }
+
+        while self.segments.len() > 1 {
+            let oldest: &Segment = &self.segments[0];
+            let too_big: bool = retention.max_bytes.is_some_and(|max| total > max);
+            let too_old: bool = retention
+                .max_age
+                .is_some_and(|max_age| older_than(oldest, max_age));
+            if !too_big && !too_old {
+                break;
+            }
+            total -= oldest.size();
+            while !self.offloaded.is_empty() {
+                self.remove_oldest()?;
+            }
+            self.remove_oldest()?;
+        }
+        Ok(())
+    }
+
+    // Deletes every segment whose records all sit below `lowest`, once
+    // downstream consumers no longer need them. The active segment is never
+    // deleted.
+    pub fn truncate(&mut self, lowest: u64) -> Result<(), Error> {
+        while !self.offloaded.is_empty() {
+            let next_base_offset: u64 = match self.offloaded.get(1) {
+                Some(&base_offset) => base_offset,
+                None => self.segments[0].base_offset(),
+            };
+            if next_base_offset > lowest {
+                return Ok(());
+            }
+            self.remove_oldest()?;
+        }
+        while self.segments.len() > 1 && self.segments[0].next_offset() <= lowest {
+            self.remove_oldest()?;
+        }
+        Ok(())
+    }
+
+    // Uploads the closed segments the object store does not hold yet, then
+    // deletes the local copies of uploaded segments whose last record is
+    // older than the hot retention, oldest first. Does nothing unless
+    // tiering is configured.
+    pub fn offload(&mut self) -> Result<(), Error> {
+        let uploaded: Uploaded = self.prepare_offload()?.run()?;
+        self.finish_offload(uploaded)
+    }
+
+    // Stages the closed segments that still need uploading, so they can be
+    // uploaded without holding the log.
+    fn prepare_offload(&self) -> Result<Offload, Error> {
+        let Some(objects) = self.object_store() else {
+            return Ok(Offload {
+                objects: None,
+                uploads: Vec::new(),
+                rewrites: self.rewrites,
+            });
+        };
+        let staging: PathBuf = self.dir.join(UPLOAD_DIR);
+        let active: usize = self.segments.len() - 1;
+        let uploads = self.segments[..active]
+            .iter()
+            .filter(|segment| !self.uploaded.contains(&segment.base_offset()))
+            .map(|segment| segment.stage_upload(&staging))
+            .collect::<Result<Vec<Upload>, Error>>()?;
+        Ok(Offload {
+            objects: Some(objects),
+            uploads,
+            rewrites: self.rewrites,
+        })
+    }
+
+    // Records what was uploaded and offloads the segments that are cold.
+    // Uploads of segments deleted meanwhile are taken back out of the object
+    // store, and uploads that raced a compaction are done again next time.
+    fn finish_offload(&mut self, uploaded: Uploaded) -> Result<(), Error> {
+        let Some(tiering) = self.config.tiering.clone() else {
+            return Ok(());
+        };
+        let active: usize = self.segments.len() - 1;
+        for base_offset in uploaded.base_offsets {
+            if !self.segments[..active]
+                .iter()
+                .any(|segment| segment.base_offset() == base_offset)
+            {
+                segment::remove_remote(tiering.store.as_ref(), base_offset)?;
+            } else if uploaded.rewrites == self.rewrites {
+                self.uploaded.insert(base_offset);
+            }
+        }
+
+        while self.segments.len() > 1 {
+            let oldest: &Segment = &self.segments[0];
+            if !self.uploaded.contains(&oldest.base_offset())
+                || !older_than(oldest, tiering.hot_retention)
+            {
+                break;
+            }
+            let segment: Segment = self.segments.remove(0);
+            self.uploaded.remove(&segment.base_offset());
+            self.offloaded.push(segment.base_offset());
+            segment.remove()?;
+        }
+        Ok(())
+    }
+
+    // Compacts every closed segment down to the latest record of each key,
+    // dropping tombstones once they have outlived the tombstone retention.
+    // Offsets are preserved, so reading a removed record fails with
+    // `Error::Compacted`. The active segment is never compacted.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let compacted: Vec<Compacted> = self.prepare_compaction()?.run()?;
+        self.finish_compaction(compacted)
+    }
+
+    // Captures the segments as they stand so they can be compacted without
+    // holding the log.
+    fn prepare_compaction(&self) -> Result<Compaction, Error> {
+        Ok(Compaction {
+            segments: self
+                .segments
+                .iter()
+                .map(Segment::batches)
+                .collect::<Result<_, _>>()?,
+            tombstone_retention: self.config.compaction.tombstone_retention,
+        })
+    }
+
+    // Swaps in the rewritten segments. Any segment deleted or offloaded since
+    // the compaction was prepared has its rewrite thrown away.
+    fn finish_compaction(&mut self, compacted: Vec<Compacted>) -> Result<(), Error> {
+        for compacted in compacted {
+            let base_offset: u64 = compacted.base_offset();
+            let Some(segment) = self
+                .segments
+                .iter_mut()
+                .find(|segment| segment.base_offset() == base_offset)
+            else {
+                compacted.discard()?;
+                continue;
+            };
+            segment.replace(compacted)?;
+            // The object store's copy is out of date and is uploaded again.
+            self.uploaded.remove(&base_offset);
+            self.rewrites += 1;
+        }
+        Ok(())
+    }
+
+    pub fn close(mut self) -> Result<(), Error> {
+        for segment in &mut self.segments {
+            segment.close()?;
+        }
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+        Ok(())
+    }
+
+    // Makes a new segment active. The outgoing segment is synced first since
+    // later syncs only cover the active segment.
+    fn roll(&mut self) -> Result<(), Error> {
+        let active = self.segments.last_mut().expect("log has no segments");
+        active.sync()?;
+        active.seal()?;
+        let next_offset: u64 = self.active_segment().next_offset();
+        self.new_segment(next_offset)?;
+        self.save_stats()
+    }
+
+    // Picks up the stats saved when the log last rolled and adds what the
+    // active segment holds. Without saved stats for the active segment, as
+    // after a crash mid-roll or in a log written by an older release, every
+    // local segment is counted instead.
+    fn load_stats(&self) -> Result<Stats, Error> {
+        let active: &Segment = self.active_segment();
+        let saved: Option<SavedStats> = match fs::read(self.dir.join(STATS_FILE)) {
+            Ok(json) => serde_json::from_slice(&json).ok(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        let (mut stats, counted) = match saved {
+            Some(saved) if saved.active == active.base_offset() => {
+                (saved.stats, std::slice::from_ref(active))
+            }
+            _ => (Stats::default(), &self.segments[..]),
+        };
+        for segment in counted {
+            let segment_stats: Stats = segment.stats()?;
+            stats.raw_bytes += segment_stats.raw_bytes;
+            stats.stored_bytes += segment_stats.stored_bytes;
+        }
+        Ok(stats)
+    }
+
+    // Saves the stats of the segments before the one just rolled to. The
+    // file is replaced whole so a crash leaves either the old stats or the
+    // new ones.
+    fn save_stats(&self) -> Result<(), Error> {
+        let saved = SavedStats {
+            active: self.active_segment().base_offset(),
+            stats: self.stats,
+        };
+        let json: Vec<u8> = serde_json::to_vec(&saved).map_err(io::Error::other)?;
+        let staged: PathBuf = self.dir.join(format!("{STATS_FILE}.tmp"));
+        fs::write(&staged, json)?;
+        Ok(fs::rename(staged, self.dir.join(STATS_FILE))?)
+    }
+
+    fn new_segment(&mut self, base_offset: u64) -> Result<(), Error> {
+        let segment = Segment::new(&self.dir, base_offset, &self.config, &self.keys)?;
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    fn active_segment(&self) -> &Segment {
+        self.segments.last().expect("log has no segments")
+    }
+
+    fn object_store(&self) -> Option<Arc<dyn ObjectStore>> {
+        self.config
+            .tiering
+            .as_ref()
+            .map(|tiering| tiering.store.clone())
+    }
+
+    // Deletes the oldest segment, offloaded or local, along with its copy in
+    // the object store.
+    fn remove_oldest(&mut self) -> Result<(), Error> {
+        let objects: Option<Arc<dyn ObjectStore>> = self.object_store();
+        if !self.offloaded.is_empty() {
+            let base_offset: u64 = self.offloaded.remove(0);
+            if let Some(cache) = &self.cache {
+                cache.evict(base_offset);
+            }
+            let objects = objects.expect("offloaded segments imply tiering");
+            return segment::remove_remote(objects.as_ref(), base_offset);
+        }
+        let segment: Segment = self.segments.remove(0);
+        if let (true, Some(objects)) = (self.uploaded.remove(&segment.base_offset()), objects) {
+            segment::remove_remote(objects.as_ref(), segment.base_offset())?;
+        }
+        segment.remove()
+    }
+
+    // What a read needs to go on into the offloaded segment at `index`.
+    fn fetch(&self, index: usize) -> Fetch {
+        Fetch {
+            cache: self
+                .cache
+                .clone()
+                .expect("offloaded segments imply tiering"),
+            base_offset: self.offloaded[index],
+            next_base_offset: match self.offloaded.get(index + 1) {
+                Some(&base_offset) => base_offset,
+                None => self.segments[0].base_offset(),
+            },
+        }
+    }
+
+    // Segments are sorted by base offset, so the one holding `offset` is the
+    // last segment that starts at or before it. Compaction can leave a
+    // closed segment without records near its end, but it still covers every
+    // offset up to where the next segment starts.
+    fn segment_index(&self, offset: u64) -> Result<Tier, Error> {
+        let lowest_offset: u64 = self.lowest_offset();
+        if offset < lowest_offset {
+            return Err(Error::OffsetTruncated {
+                offset,
+                lowest_offset,
+            });
+        }
+        if offset >= self.active_segment().next_offset() {
+            return Err(Error::OffsetOutOfRange(offset));
+        }
+        if offset < self.segments[0].base_offset() {
+            let after: usize = self
+                .offloaded
+                .partition_point(|&base_offset| base_offset <= offset);
+            return Ok(Tier::Offloaded(after - 1));
+        }
+        let after: usize = self
+            .segments
+            .partition_point(|segment| segment.base_offset() <= offset);
+        Ok(Tier::Local(after - 1))
+    }
+}
+
+// What compaction works from: a view of every segment, the active one last.
+// The active segment is only read, for the latest record of each key.
+struct Compaction {
+    segments: Vec<Batches>,
+    tombstone_retention: Duration,
+}
+
+impl Compaction {
+    // Stages a rewrite of every closed segment that holds records to drop.
+    fn run(self) -> Result<Vec<Compacted>, Error> {
+        let mut latest: HashMap<Bytes, u64> = HashMap::new();
+        for segment in &self.segments {
+            let mut pos: u64 = segment.start();
+            while let Some((records, next_pos)) = segment.read_batch(pos)? {
+                for record in records {
+                    if let Some(key) = record.key {
+                        latest.insert(key, record.offset);
+                    }
+                }
+                pos = next_pos;
+            }
+        }
+
+        let retention: u64 = self.tombstone_retention.as_millis() as u64;
+        let horizon: u64 = now_millis().saturating_sub(retention);
+        let active: usize = self.segments.len() - 1;
+        let mut compacted: Vec<Compacted> = Vec::new();
+        for segment in &self.segments[..active] {
+            let rewrite = segment.compact(|record| match &record.key {
+                Some(key) => {
+                    latest.get(key) == Some(&record.offset)
+                        && (record.value.is_some() || record.timestamp >= horizon)
+                }
+                None => true,
+            })?;
+            compacted.extend(rewrite);
+        }
+        Ok(compacted)
+    }
+}
+
+// What the log held before a write.
+struct Mark {
+    segments: usize,
+    active: segment::Mark,
+    last_timestamp: u64,
+    unsynced: u64,
+    stats: Stats,
+}
+
+// Where the segment holding an offset lives, as an index into either the
+// local segments or the offloaded ones.
+enum Tier {
+    Local(usize),
+    Offloaded(usize),
+}
+
+// How far a read got under the log's lock: either it is done, or it stopped
+// at an offloaded segment that has to be fetched before it can go on.
+enum Progress<T> {
+    Done(T),
+    Fetch(Fetch),
+}
+
+// An offloaded segment a read stopped at, along with where the segment after
+// it starts.
+struct Fetch {
+    cache: Arc<SegmentCache>,
+    base_offset: u64,
+    next_base_offset: u64,
+}
+
+impl Fetch {
+    fn segment(&self) -> Result<Arc<Fetched>, Error> {
+        self.cache.get(self.base_offset)
+    }
+}
+
+// What `offset_for_time` works from: the offloaded segments to search, if
+// the record may be among theirs, and the answer from the local ones.
+struct TimeSearch {
+    objects: Option<Arc<dyn ObjectStore>>,
+    probes: PathBuf,
+    offloaded: Vec<u64>,
+    timestamp: u64,
+    local: u64,
+}
+
+impl TimeSearch {
+    // Binary searches for the first offloaded segment with a record at or
+    // after the timestamp, probing only their time indexes.
+    fn run(self) -> Result<u64, Error> {
+        let (mut low, mut high) = (0, self.offloaded.len());
+        let mut first: Option<u64> = None;
+        while low < high {
+            let objects = self
+                .objects
+                .as_ref()
+                .expect("offloaded segments imply tiering");
+            let mid: usize = low + (high - low) / 2;
+            match segment::remote_offset_for_time(
+                objects.as_ref(),
+                &self.probes,
+                self.offloaded[mid],
+                self.timestamp,
+            )? {
+                Some(offset) => {
+                    first = Some(offset);
+                    high = mid;
+                }
+                None => low = mid + 1,
+            }
+        }
+        Ok(first.unwrap_or(self.local))
+    }
+}
+
+// Closed segments staged for upload by `prepare_offload`.
+struct Offload {
+    objects: Option<Arc<dyn ObjectStore>>,
+    uploads: Vec<Upload>,
+    rewrites: u64,
+}
+
+impl Offload {
+    // Uploads every staged segment. After a failed upload the rest are
+    // discarded and left for the next run.
+    fn run(self) -> Result<Uploaded, Error> {
+        let mut uploaded = Uploaded {
+            base_offsets: Vec::new(),
+            rewrites: self.rewrites,
+        };
+        let Some(objects) = self.objects else {
+            return Ok(uploaded);
+        };
+        let mut uploads = self.uploads.into_iter();
+        for upload in uploads.by_ref() {
+            let base_offset: u64 = upload.base_offset();
+            if let Err(err) = upload.run(objects.as_ref()) {
+                for upload in uploads {
+                    upload.discard()?;
+                }
+                return Err(err);
+            }
+            uploaded.base_offsets.push(base_offset);
+        }
+        Ok(uploaded)
+    }
+}
+
+// The segments an `Offload` copied to the object store.
+struct Uploaded {
+    base_offsets: Vec<u64>,
+    rewrites: u64,
+}
+
+// The records gathered so far by `read_range`.
+struct Page {
+    records: Vec<Record>,
+    bytes: u64,
+    start: u64,
+    max_records: usize,
+    max_bytes: u64,
+}
+
+impl Page {
+    fn new(start: u64, max_records: usize, max_bytes: u64) -> Self {
+        Self {
+            records: Vec::new(),
+            bytes: 0,
+            start,
+            max_records,
+            max_bytes,
+        }
+    }
+
+    // Adds the offloaded segment's records like `fill`, then moves the
+    // page's start on to the segment after it.
+    fn fill_offloaded(&mut self, fetch: Fetch) -> Result<Option<u64>, Error> {
+        let fetched: Arc<Fetched> = fetch.segment()?;
+        if let Some(next) = self.fill(&fetched)? {
+            return Ok(Some(next));
+        }
+        self.start = fetch.next_base_offset;
+        Ok(None)
+    }
+
+    // Adds the segment's records from `start` on until the page is full, and
+    // returns the offset to continue from if it fills up.
+    fn fill(&mut self, segment: &Segment) -> Result<Option<u64>, Error> {
+        // Compaction can leave nothing at or after `start` in a segment.
+        let Some(mut pos) = segment.seek(self.start.max(segment.base_offset())) else {
+            return Ok(None);
+        };
+        while let Some((batch, next_pos)) = segment.read_batch(pos)? {
+            for record in batch
+                .into_iter()
+                .filter(|record| record.offset >= self.start)
+            {
+                let len: u64 = record.value.as_ref().map_or(0, |value| value.len() as u64);
+                if self.records.len() >= self.max_records
+                    || (!self.records.is_empty() && self.bytes + len > self.max_bytes)
+                {
+                    return Ok(Some(record.offset));
+                }
+                self.bytes += len;
+                self.records.push(record);
+            }
+            pos = next_pos;
+        }
+        Ok(None)
+    }
+}
+
+// Takes the lock on the log in `dir` for as long as the returned file stays
+// open, failing with `Error::Locked` if another process holds it.
+fn lock(dir: &Path) -> Result<File, Error> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(Error::Locked {
+            dir: dir.to_path_buf(),
+        }),
+        Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+}
+
+// Base offsets of the segments stored in `dir`, in order.
+fn base_offsets(dir: &Path) -> Result<Vec<u64>, Error> {
+    let mut base_offsets: Vec<u64> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path: PathBuf = entry?.path();
+        let is_segment_file = matches!(
+            path.extension().and_then(OsStr::to_str),
+            Some("store" | "index")
+        );
+        let base_offset = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .and_then(|stem| stem.parse::<u64>().ok());
+        if let (true, Some(base_offset)) = (is_segment_file, base_offset) {
+            base_offsets.push(base_offset);
+        }
+    }
+    base_offsets.sort_unstable();
+    base_offsets.dedup();
+    Ok(base_offsets)
+}
+
+// Whether the segment's latest record was appended more than `age` ago. Ages
+// go by record timestamps, which unlike file times survive compaction and
+// restores.
+fn older_than(segment: &Segment, age: Duration) -> bool {
+    let horizon: u64 = now_millis().saturating_sub(age.as_millis() as u64);
+    segment.max_timestamp().is_none_or(|last| last < horizon)
+}
+
+// Milliseconds since the Unix epoch.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+// Record values, keys and header values are raw bytes, so they travel through
+// JSON as base64 strings.
+mod base64_value {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        let encoded: String = String::deserialize(deserializer)?;
+        let value: Vec<u8> = STANDARD.decode(encoded).map_err(serde::de::Error::custom)?;
+        Ok(Bytes::from(value))
+    }
+}
+
+mod base64_option {
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Bytes>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => super::base64_value::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Bytes>, D::Error> {
+        #[derive(Deserialize)]
+        struct Value(#[serde(with = "super::base64_value")] Bytes);
+
+        let value: Option<Value> = Option::deserialize(deserializer)?;
+        Ok(value.map(|Value(value)| value))
+    }
+}
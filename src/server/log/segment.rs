@@ -0,0 +1,840 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use super::config::Config;
+use super::encryption::Keyring;
+use super::error::Error;
+use super::index::Index;
+use super::snapshot::Entry;
+use super::store::{Store, COMPRESSION_VERSION, ENCRYPTION_VERSION, FRAME_WIDTH};
+use super::tiered::ObjectStore;
+use super::timeindex::TimeIndex;
+use super::{batch, compression, Record, Stats};
+
+// Compacted segments are built in this subdirectory of the log before they
+// replace the originals.
+const COMPACTION_DIR: &str = "compaction";
+
+// Indexes rebuilt by `Segment::rebuild` are written under their usual name
+// with this suffix until they are complete.
+const REBUILD_SUFFIX: &str = ".rebuild";
+
+// Time indexes probed in the object store are downloaded under this counter
+// so concurrent probes of one segment do not collide.
+static NEXT_PROBE: AtomicU64 = AtomicU64::new(0);
+
+// A segment's files in the order they are uploaded to an object store. The
+// store goes last, so a segment only counts as uploaded once its store
+// object exists, and is deleted first.
+const EXTENSIONS: [&str; 3] = ["index", "timeindex", "store"];
+
+// How far a segment's files reached at some point.
+#[derive(Clone, Copy, Debug)]
+pub struct Mark {
+    store_bytes: u64,
+    entries: u64,
+    next_offset: u64,
+}
+
+// A segment pairs a store with the index of its records and a time index of
+// when they were appended. All three files are named after the offset of the
+// segment's first record.
+pub struct Segment {
+    store: Store,
+    index: Index,
+    time_index: TimeIndex,
+    store_path: PathBuf,
+    index_path: PathBuf,
+    time_index_path: PathBuf,
+    base_offset: u64,
+    next_offset: u64,
+    config: Config,
+    keys: Arc<Keyring>,
+}
+
+impl Segment {
+    pub fn new(
+        dir: &Path,
+        base_offset: u64,
+        config: &Config,
+        keys: &Arc<Keyring>,
+    ) -> Result<Self, Error> {
+        let path = |extension: &str| dir.join(format!("{base_offset}.{extension}"));
+        Self::open(
+            path("store"),
+            path("index"),
+            path("timeindex"),
+            base_offset,
+            config,
+            keys,
+        )
+    }
+
+    // Rebuilds the index and time index of the segment stored in `dir` from
+    // its store, cutting the store at its first corrupt frame. The new
+    // indexes are written under temporary names and only moved over the old
+    // ones once both are complete, so a failure leaves the old ones as they
+    // were.
+    pub fn rebuild(
+        dir: &Path,
+        base_offset: u64,
+        config: &Config,
+        keys: &Arc<Keyring>,
+    ) -> Result<(), Error> {
+        let path = |extension: &str| dir.join(format!("{base_offset}.{extension}"));
+        let staged =
+            |extension: &str| dir.join(format!("{base_offset}.{extension}{REBUILD_SUFFIX}"));
+        let extensions: [&str; 2] = ["index", "timeindex"];
+        let remove_staged = || -> io::Result<()> {
+            for extension in extensions {
+                match fs::remove_file(staged(extension)) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+            Ok(())
+        };
+        // Left over from a rebuild that was cut short.
+        remove_staged()?;
+        let rebuilt = Self::open(
+            path("store"),
+            staged("index"),
+            staged("timeindex"),
+            base_offset,
+            config,
+            keys,
+        )
+        .and_then(|mut segment| segment.close());
+        if let Err(err) = rebuilt {
+            let _ = remove_staged();
+            return Err(err);
+        }
+        for extension in extensions {
+            fs::rename(staged(extension), path(extension))?;
+        }
+        Ok(())
+    }
+
+    fn open(
+        store_path: PathBuf,
+        index_path: PathBuf,
+        time_index_path: PathBuf,
+        base_offset: u64,
+        config: &Config,
+        keys: &Arc<Keyring>,
+    ) -> Result<Self, Error> {
+        let mut options = OpenOptions::new();
+        options.read(true).create(true);
+
+        let store = Store::new(options.clone().append(true).open(&store_path)?)?;
+        let time_index = TimeIndex::new(options.clone().append(true).open(&time_index_path)?)?;
+        let first_frame: Option<u64> = (store.size() > store.start()).then_some(store.start());
+        let index = Index::new(options.write(true).open(&index_path)?, config, first_frame)?;
+
+        let mut segment = Self {
+            store,
+            index,
+            time_index,
+            store_path,
+            index_path,
+            time_index_path,
+            base_offset,
+            next_offset: base_offset,
+            config: config.clone(),
+            keys: keys.clone(),
+        };
+        segment.rebuild_index()?;
+        segment.next_offset = match segment.index.last() {
+            Some((off, _)) => base_offset + off as u64 + 1,
+            None => base_offset,
+        };
+        segment.rebuild_time_index()?;
+        Ok(segment)
+    }
+
+    // Brings the index back in line with the store so the last entry always
+    // reveals the next offset. Entries pointing at records that are missing
+    // or fail their checksum are dropped, records the index never saw are
+    // added, and a torn record left at the tail by a crash mid-write is cut
+    // off the store.
+    fn rebuild_index(&mut self) -> Result<(), Error> {
+        let mut pos: u64 = self.store.start();
+        while let Some((_, last)) = self.index.last() {
+            match self.store.read(last) {
+                Ok(data) => {
+                    pos = last + FRAME_WIDTH + data.len() as u64;
+                    break;
+                }
+                Err(Error::Corrupt { .. }) => self.index.truncate(self.index.entries() - 1),
+                Err(err) => return Err(err),
+            }
+        }
+
+        while pos < self.store.size() {
+            let data = match self.store.read(pos) {
+                Ok(data) => data,
+                Err(Error::Corrupt { .. }) => break,
+                Err(err) => return Err(err),
+            };
+            // A batch that fails to decrypt passed its checksum, so it was
+            // written whole and must not be cut off.
+            let records = match self.decode(&data, pos) {
+                Ok(records) if self.fits(&records) => records,
+                Ok(_) | Err(Error::Corrupt { .. }) => break,
+                Err(err) => return Err(err),
+            };
+            for record in records {
+                self.index
+                    .write((record.offset - self.base_offset) as u32, pos)?;
+            }
+            pos += FRAME_WIDTH + data.len() as u64;
+        }
+
+        if pos < self.store.size() {
+            self.store.truncate(pos)?;
+        }
+        Ok(())
+    }
+
+    // Drops time index entries for records the store no longer holds and adds
+    // entries for batches appended after the last one that made it to disk.
+    fn rebuild_time_index(&mut self) -> Result<(), Error> {
+        self.time_index
+            .truncate((self.next_offset - self.base_offset) as u32)?;
+        let mut pos: u64 = match self.time_index.last() {
+            Some((_, off)) => self.position(self.base_offset + off as u64)?,
+            None if self.next_offset > self.base_offset => self.store.start(),
+            None => return Ok(()),
+        };
+        loop {
+            let (records, next_pos) = match self.read_batch(pos) {
+                Ok(Some(batch)) => batch,
+                // A corrupt batch is reported when it is read; here it only
+                // ends the scan.
+                Ok(None) | Err(Error::Corrupt { .. }) => break,
+                Err(err) => return Err(err),
+            };
+            if let Some(first) = records.first() {
+                self.time_index
+                    .write(first.timestamp, (first.offset - self.base_offset) as u32)?;
+            }
+            pos = next_pos;
+        }
+        Ok(())
+    }
+
+    // Whether a decoded batch continues this segment where its index leaves
+    // off and fits in what is left of the index.
+    fn fits(&self, records: &[Record]) -> bool {
+        let next_offset: u64 = match self.index.last() {
+            Some((off, _)) => self.base_offset + off as u64 + 1,
+            None => self.base_offset,
+        };
+        records
+            .first()
+            .is_some_and(|record| record.offset >= next_offset)
+            && records.len() as u64 <= self.index.free_entries()
+    }
+
+    // Appends the records as a single batch, assigning them consecutive
+    // offsets and the append `timestamp`, and returns the first and last
+    // offset. The batch lands in the store as one frame, so it is either
+    // persisted whole or not at all. Its size before and after compression
+    // is added to `stats`.
+    pub fn append(
+        &mut self,
+        mut records: Vec<Record>,
+        timestamp: u64,
+        stats: &mut Stats,
+    ) -> Result<(u64, u64), Error> {
+        if (records.len() as u64) > self.index.free_entries() {
+            return Err(Error::SegmentFull {
+                base_offset: self.base_offset,
+            });
+        }
+        let first: u64 = self.next_offset;
+        for (i, record) in records.iter_mut().enumerate() {
+            record.offset = first + i as u64;
+            record.timestamp = timestamp;
+        }
+        let last: u64 = first + records.len() as u64 - 1;
+        self.write_batch(&records, stats)?;
+        Ok((first, last))
+    }
+
+    // Writes records that already carry their offsets and timestamp as one
+    // batch, compressed with the configured codec and encrypted with the
+    // active key as far as the store's format version supports. If any of
+    // the segment's files fails the write, whatever reached the others is
+    // taken back so the next batch reuses the same offsets and position.
+    fn write_batch(&mut self, records: &[Record], stats: &mut Stats) -> Result<(), Error> {
+        let encoded: Vec<u8> = batch::encode(records);
+        let raw_bytes: u64 = encoded.len() as u64;
+        let mut data: Vec<u8> = encoded;
+        if self.store.version() >= COMPRESSION_VERSION {
+            data = compression::compress(&self.config.compression, &data)?;
+        }
+        let stored_bytes: u64 = data.len() as u64;
+        if self.store.version() >= ENCRYPTION_VERSION {
+            data = self.keys.encrypt(&data)?;
+        }
+        let mark: Mark = self.mark();
+        if let Err(err) = self.write_frame(records, &data) {
+            self.rollback(mark)?;
+            return Err(err);
+        }
+        stats.raw_bytes += raw_bytes;
+        stats.stored_bytes += stored_bytes;
+        Ok(())
+    }
+
+    fn write_frame(&mut self, records: &[Record], data: &[u8]) -> Result<(), Error> {
+        let (_, pos) = self.store.append(data)?;
+        for record in records {
+            self.index
+                .write((record.offset - self.base_offset) as u32, pos)?;
+        }
+        if let (Some(first), Some(last)) = (records.first(), records.last()) {
+            self.time_index
+                .write(first.timestamp, (first.offset - self.base_offset) as u32)?;
+            self.next_offset = last.offset + 1;
+        }
+        Ok(())
+    }
+
+    // Where the segment's files end, for undoing later writes with
+    // `rollback`.
+    pub fn mark(&self) -> Mark {
+        Mark {
+            store_bytes: self.store.size(),
+            entries: self.index.entries(),
+            next_offset: self.next_offset,
+        }
+    }
+
+    // Drops every batch written since `mark` was taken. A segment sealed
+    // since is reopened for appends.
+    pub fn rollback(&mut self, mark: Mark) -> Result<(), Error> {
+        self.store.unseal();
+        self.store.truncate(mark.store_bytes)?;
+        self.index.truncate(mark.entries);
+        self.time_index
+            .truncate((mark.next_offset - self.base_offset) as u32)?;
+        self.next_offset = mark.next_offset;
+        Ok(())
+    }
+
+    // A view of the segment's batches as they stand, which can be read
+    // without the log's lock.
+    pub fn batches(&self) -> Result<Batches, Error> {
+        Ok(Batches {
+            store: self.store.view()?,
+            dir: self.dir(),
+            base_offset: self.base_offset,
+            config: self.config.clone(),
+            keys: self.keys.clone(),
+        })
+    }
+
+    // Swaps in a rewrite of the segment staged by `Batches::compact`. The old
+    // index is removed first and the new one moved in last, so a crash part
+    // way through leaves a store whose index is rebuilt on the next open.
+    pub fn replace(&mut self, compacted: Compacted) -> Result<(), Error> {
+        self.close()?;
+        fs::remove_file(&self.index_path)?;
+        fs::remove_file(&self.time_index_path)?;
+        for extension in ["store", "timeindex", "index"] {
+            let name: String = format!("{}.{extension}", self.base_offset);
+            fs::rename(compacted.staging.join(&name), self.dir().join(&name))?;
+        }
+        *self = Segment::new(&self.dir(), self.base_offset, &self.config, &self.keys)?;
+        self.seal()
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.store_path
+            .parent()
+            .expect("segment files live in the log directory")
+            .to_path_buf()
+    }
+
+    pub fn read(&self, offset: u64) -> Result<Record, Error> {
+        let pos: u64 = self.position(offset)?;
+        let (records, _) = self
+            .read_batch(pos)?
+            .ok_or(Error::Corrupt { position: pos })?;
+        records
+            .into_iter()
+            .find(|record| record.offset == offset)
+            .ok_or(Error::Corrupt { position: pos })
+    }
+
+    // Returns the store position of the batch holding `offset`.
+    pub fn position(&self, offset: u64) -> Result<u64, Error> {
+        self.index
+            .find((offset - self.base_offset) as u32)
+            .ok_or(Error::Compacted { offset })
+    }
+
+    // Returns the store position of the batch holding the first record at or
+    // after `offset`, if the segment has one.
+    pub fn seek(&self, offset: u64) -> Option<u64> {
+        let index: u64 = self.index.seek((offset - self.base_offset) as u32);
+        let (_, pos) = self.index.read(index).ok()?;
+        Some(pos)
+    }
+
+    // Captures the part of the segment a snapshot up to `upto` covers: every
+    // batch starting at or before `upto`, since a batch is never split. The
+    // store is reopened so the snapshot can read it even after compaction or
+    // retention replaces or deletes the file. `upto` must not be below the
+    // segment's base offset.
+    pub fn snapshot(&self, upto: u64) -> Result<Entry, Error> {
+        self.store.flush()?;
+        let file = File::open(&self.store_path)?;
+        let (len, next_offset) = if upto >= self.next_offset {
+            (self.store.size(), self.next_offset)
+        } else {
+            let index: u64 = self.index.seek((upto - self.base_offset) as u32);
+            // The offset after the last record indexed before `index`.
+            let next_offset: u64 = match index.checked_sub(1) {
+                Some(previous) => self.base_offset + self.index.read(previous)?.0 as u64 + 1,
+                None => self.base_offset,
+            };
+            match self.index.read(index) {
+                Ok((_, pos)) => {
+                    let (records, next_pos) = self
+                        .read_batch(pos)?
+                        .ok_or(Error::Corrupt { position: pos })?;
+                    match (records.first(), records.last()) {
+                        (Some(first), Some(last)) if first.offset <= upto => {
+                            (next_pos, last.offset + 1)
+                        }
+                        _ => (pos, next_offset),
+                    }
+                }
+                Err(_) => (self.store.size(), next_offset),
+            }
+        };
+        Ok(Entry {
+            base_offset: self.base_offset,
+            file,
+            len,
+            next_offset,
+        })
+    }
+
+    // Sizes of the batches the segment holds, counted as they were when
+    // appended. Counting stops at the first batch that cannot be read back,
+    // which reads report on their own.
+    pub fn stats(&self) -> Result<Stats, Error> {
+        let mut stats = Stats::default();
+        let mut pos: u64 = self.store.start();
+        while pos < self.store.size() {
+            let data: Bytes = match self.store.read(pos) {
+                Ok(data) => data,
+                Err(Error::Io(err)) => return Err(Error::Io(err)),
+                Err(_) => break,
+            };
+            let Ok((stored, batch)) = unwrap_frame(self.store.version(), &self.keys, &data, pos)
+            else {
+                break;
+            };
+            stats.raw_bytes += batch.len() as u64;
+            stats.stored_bytes += stored.len() as u64;
+            pos += FRAME_WIDTH + data.len() as u64;
+        }
+        Ok(stats)
+    }
+
+    // Position of the segment's first batch in its store.
+    pub fn start(&self) -> u64 {
+        self.store.start()
+    }
+
+    // Reads the batch stored at `pos` along with the position of the batch
+    // after it, or None once `pos` reaches the end of the store.
+    pub fn read_batch(&self, pos: u64) -> Result<Option<(Vec<Record>, u64)>, Error> {
+        if pos >= self.store.size() {
+            return Ok(None);
+        }
+        let data = self.store.read(pos)?;
+        let records = self.decode(&data, pos)?;
+        Ok(Some((records, pos + FRAME_WIDTH + data.len() as u64)))
+    }
+
+    fn decode(&self, data: &Bytes, pos: u64) -> Result<Vec<Record>, Error> {
+        decode(self.store.version(), &self.keys, data, pos)
+    }
+
+    // Whether the index has room for another batch of `records` records.
+    pub fn has_room_for(&self, records: u64) -> bool {
+        records <= self.index.free_entries()
+    }
+
+    // A segment is maxed once either its store or its index has reached the
+    // configured size, at which point the log rolls to a new segment.
+    pub fn is_maxed(&self) -> bool {
+        self.store.size() >= self.config.segment.max_store_bytes || self.index.is_full()
+    }
+
+    // Bytes of record data held in the segment's store.
+    pub fn size(&self) -> u64 {
+        self.store.size()
+    }
+
+    // Returns the offset of the first record appended at or after
+    // `timestamp`, if the segment holds one.
+    pub fn offset_for_time(&self, timestamp: u64) -> Option<u64> {
+        let off: u32 = self.time_index.lookup(timestamp)?;
+        Some(self.base_offset + off as u64)
+    }
+
+    // The append timestamp of the segment's first record.
+    pub fn min_timestamp(&self) -> Option<u64> {
+        self.time_index.first().map(|(timestamp, _)| timestamp)
+    }
+
+    // The append timestamp of the segment's most recent record.
+    pub fn max_timestamp(&self) -> Option<u64> {
+        self.time_index.last().map(|(timestamp, _)| timestamp)
+    }
+
+    // The format version the segment's store is written in.
+    pub fn version(&self) -> u32 {
+        self.store.version()
+    }
+
+    pub fn base_offset(&self) -> u64 {
+        self.base_offset
+    }
+
+    pub fn next_offset(&self) -> u64 {
+        self.next_offset
+    }
+
+    pub fn flush(&self) -> Result<(), Error> {
+        Ok(self.store.flush()?)
+    }
+
+    // Marks the segment as closed for appends so reads can be served
+    // straight out of a memory map of its store.
+    pub fn seal(&mut self) -> Result<(), Error> {
+        Ok(self.store.seal()?)
+    }
+
+    pub fn sync(&self) -> Result<(), Error> {
+        self.store.sync()?;
+        self.time_index.sync()?;
+        Ok(self.index.sync()?)
+    }
+
+    pub fn close(&mut self) -> Result<(), Error> {
+        self.store.sync()?;
+        self.time_index.sync()?;
+        Ok(self.index.close()?)
+    }
+
+    // Closes the segment and deletes its files.
+    pub fn remove(mut self) -> Result<(), Error> {
+        self.close()?;
+        fs::remove_file(&self.index_path)?;
+        fs::remove_file(&self.time_index_path)?;
+        Ok(fs::remove_file(&self.store_path)?)
+    }
+
+    // Stages the closed segment's files in `dir` so `Upload::run` can copy
+    // them to the object store without holding the log. The store and time
+    // index no longer change once the segment is closed, so they are linked
+    // rather than copied, and the index is taken trimmed to its entries.
+    pub fn stage_upload(&self, dir: &Path) -> Result<Upload, Error> {
+        fs::create_dir_all(dir)?;
+        let upload = Upload {
+            dir: dir.to_path_buf(),
+            base_offset: self.base_offset,
+            index: self.index.bytes().to_vec(),
+        };
+        // Leftovers from an upload cut short.
+        upload.discard()?;
+        fs::hard_link(&self.time_index_path, upload.path("timeindex"))?;
+        fs::hard_link(&self.store_path, upload.path("store"))?;
+        Ok(upload)
+    }
+
+    // Downloads an offloaded segment from the object store into `dir` and
+    // opens it for reads.
+    pub fn fetch(
+        objects: &dyn ObjectStore,
+        dir: &Path,
+        base_offset: u64,
+        config: &Config,
+        keys: &Arc<Keyring>,
+    ) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+        for extension in EXTENSIONS {
+            let name: String = object_name(base_offset, extension);
+            objects.download(&name, &dir.join(&name))?;
+        }
+        let mut segment = Segment::new(dir, base_offset, config, keys)?;
+        segment.seal()?;
+        Ok(segment)
+    }
+}
+
+// A read-only view of a segment's batches, taken by `Segment::batches`. It
+// stays readable after the segment itself is compacted, offloaded or
+// deleted.
+pub struct Batches {
+    store: Store,
+    dir: PathBuf,
+    base_offset: u64,
+    config: Config,
+    keys: Arc<Keyring>,
+}
+
+impl Batches {
+    pub fn base_offset(&self) -> u64 {
+        self.base_offset
+    }
+
+    // Position of the first batch.
+    pub fn start(&self) -> u64 {
+        self.store.start()
+    }
+
+    // Reads the batch stored at `pos` along with the position of the batch
+    // after it, or None once `pos` reaches the end of the view.
+    pub fn read_batch(&self, pos: u64) -> Result<Option<(Vec<Record>, u64)>, Error> {
+        if pos >= self.store.size() {
+            return Ok(None);
+        }
+        let data = self.store.read(pos)?;
+        let records = decode(self.store.version(), &self.keys, &data, pos)?;
+        Ok(Some((records, pos + FRAME_WIDTH + data.len() as u64)))
+    }
+
+    // Stages a rewrite of the segment with only the records `keep` accepts,
+    // at their original offsets and timestamps, for `Segment::replace` to
+    // swap in. Returns None if every record is kept. The rewrite is always
+    // in the current format, which upgrades segments written by older
+    // releases.
+    pub fn compact(
+        &self,
+        mut keep: impl FnMut(&Record) -> bool,
+    ) -> Result<Option<Compacted>, Error> {
+        let mut batches: Vec<Vec<Record>> = Vec::new();
+        let mut dropped: u64 = 0;
+        let mut pos: u64 = self.start();
+        while let Some((records, next_pos)) = self.read_batch(pos)? {
+            let count: usize = records.len();
+            let kept: Vec<Record> = records.into_iter().filter(|record| keep(record)).collect();
+            dropped += (count - kept.len()) as u64;
+            if !kept.is_empty() {
+                batches.push(kept);
+            }
+            pos = next_pos;
+        }
+        if dropped == 0 {
+            return Ok(None);
+        }
+
+        let staging: PathBuf = self.dir.join(COMPACTION_DIR);
+        fs::create_dir_all(&staging)?;
+        let compacted = Compacted {
+            staging,
+            base_offset: self.base_offset,
+        };
+        // Leftovers from a compaction cut short by a crash.
+        compacted.discard()?;
+
+        let mut segment = Segment::new(
+            &compacted.staging,
+            self.base_offset,
+            &self.config,
+            &self.keys,
+        )?;
+        let mut stats = Stats::default();
+        for batch in &batches {
+            segment.write_batch(batch, &mut stats)?;
+        }
+        segment.close()?;
+        Ok(Some(compacted))
+    }
+}
+
+// A segment rewritten by compaction, staged aside until it replaces the
+// original.
+pub struct Compacted {
+    staging: PathBuf,
+    base_offset: u64,
+}
+
+impl Compacted {
+    pub fn base_offset(&self) -> u64 {
+        self.base_offset
+    }
+
+    // Deletes the staged files, for when the original is gone by the time
+    // the rewrite is ready.
+    pub fn discard(&self) -> Result<(), Error> {
+        for extension in ["store", "index", "timeindex"] {
+            let path: PathBuf = self
+                .staging
+                .join(format!("{}.{extension}", self.base_offset));
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn object_name(base_offset: u64, extension: &str) -> String {
+    format!("{base_offset}.{extension}")
+}
+
+// Base offsets of the segments the object store holds, in order.
+pub fn remote_base_offsets(objects: &dyn ObjectStore) -> Result<Vec<u64>, Error> {
+    let mut base_offsets: Vec<u64> = objects
+        .list()?
+        .iter()
+        .filter_map(|name| name.strip_suffix(".store")?.parse().ok())
+        .collect();
+    base_offsets.sort_unstable();
+    Ok(base_offsets)
+}
+
+// Deletes a segment's files from the object store.
+pub fn remove_remote(objects: &dyn ObjectStore, base_offset: u64) -> Result<(), Error> {
+    for extension in EXTENSIONS.iter().rev() {
+        objects.delete(&object_name(base_offset, extension))?;
+    }
+    Ok(())
+}
+
+// The append timestamp of an offloaded segment's most recent record, read
+// from its time index alone so the store need not be downloaded.
+pub fn remote_max_timestamp(
+    objects: &dyn ObjectStore,
+    dir: &Path,
+    base_offset: u64,
+) -> Result<Option<u64>, Error> {
+    let time_index: TimeIndex = remote_time_index(objects, dir, base_offset)?;
+    Ok(time_index.last().map(|(timestamp, _)| timestamp))
+}
+
+// The offset of an offloaded segment's first record appended at or after
+// `timestamp`, if it has one, read from its time index alone.
+pub fn remote_offset_for_time(
+    objects: &dyn ObjectStore,
+    dir: &Path,
+    base_offset: u64,
+    timestamp: u64,
+) -> Result<Option<u64>, Error> {
+    let time_index: TimeIndex = remote_time_index(objects, dir, base_offset)?;
+    Ok(time_index
+        .lookup(timestamp)
+        .map(|off| base_offset + off as u64))
+}
+
+// Downloads an offloaded segment's time index into `dir` under a name of its
+// own, so probes running side by side never share a file, and loads it.
+fn remote_time_index(
+    objects: &dyn ObjectStore,
+    dir: &Path,
+    base_offset: u64,
+) -> Result<TimeIndex, Error> {
+    fs::create_dir_all(dir)?;
+    let probe: u64 = NEXT_PROBE.fetch_add(1, Ordering::Relaxed);
+    let path: PathBuf = dir.join(format!("{base_offset}.timeindex.{probe}"));
+    let loaded = objects
+        .download(&object_name(base_offset, "timeindex"), &path)
+        .and_then(|()| {
+            let file = OpenOptions::new().read(true).append(true).open(&path)?;
+            TimeIndex::new(file)
+        });
+    match fs::remove_file(&path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+    Ok(loaded?)
+}
+
+// A closed segment's files staged by `Segment::stage_upload`.
+pub struct Upload {
+    dir: PathBuf,
+    base_offset: u64,
+    index: Vec<u8>,
+}
+
+impl Upload {
+    pub fn base_offset(&self) -> u64 {
+        self.base_offset
+    }
+
+    // Copies the staged files to the object store, named as they are on
+    // disk, and deletes them.
+    pub fn run(self, objects: &dyn ObjectStore) -> Result<(), Error> {
+        let uploaded = self.upload(objects);
+        self.discard()?;
+        uploaded
+    }
+
+    fn upload(&self, objects: &dyn ObjectStore) -> Result<(), Error> {
+        fs::write(self.path("index"), &self.index)?;
+        for extension in EXTENSIONS {
+            objects.upload(
+                &object_name(self.base_offset, extension),
+                &self.path(extension),
+            )?;
+        }
+        Ok(())
+    }
+
+    // Deletes the staged files.
+    pub fn discard(&self) -> Result<(), Error> {
+        for extension in EXTENSIONS {
+            match fs::remove_file(self.path(extension)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, extension: &str) -> PathBuf {
+        self.dir.join(object_name(self.base_offset, extension))
+    }
+}
+
+// Decodes the batch held in the frame read at `pos` from a store written in
+// format `version`.
+pub fn decode(version: u32, keys: &Keyring, data: &Bytes, pos: u64) -> Result<Vec<Record>, Error> {
+    let (_, batch) = unwrap_frame(version, keys, data, pos)?;
+    batch::decode(&batch).ok_or(Error::Corrupt { position: pos })
+}
+
+// Decrypts the frame read at `pos` and returns its compressed batch along
+// with the encoded batch it decompresses to.
+fn unwrap_frame(
+    version: u32,
+    keys: &Keyring,
+    data: &Bytes,
+    pos: u64,
+) -> Result<(Bytes, Bytes), Error> {
+    let mut stored: Bytes = data.clone();
+    if version >= ENCRYPTION_VERSION {
+        stored = keys.decrypt(&stored, pos)?;
+    }
+    let mut batch: Bytes = stored.clone();
+    if version >= COMPRESSION_VERSION {
+        batch = compression::decompress(&stored).ok_or(Error::Corrupt { position: pos })?;
+    }
+    Ok((stored, batch))
+}
@@ -0,0 +1,312 @@
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::Duration;
+
+use super::config::{Config, SyncPolicy};
+use super::error::Error;
+use super::{Log, Page, Progress, Record, Snapshot, Stats};
+
+// The most appends the committer folds into a single commit.
+const MAX_GROUP_COMMIT: usize = 1024;
+
+type Inner = Arc<RwLock<Option<Log>>>;
+
+type Offsets = Result<(u64, u64), Error>;
+
+type Reply = Sender<Offsets>;
+
+struct Append {
+    records: Vec<Record>,
+    reply: Reply,
+}
+
+// A cloneable handle that lets concurrent tasks share one Log. Reads run in
+// parallel under a read lock. Appends are handed to a background committer
+// which writes everything queued up behind the write lock and then commits
+// the whole group with a single fsync, so offsets stay dense and unique while
+// throughput grows with the number of concurrent appenders. Once closed,
+// every handle fails with `Error::Closed`.
+#[derive(Clone)]
+pub struct SharedLog {
+    inner: Inner,
+    appends: Sender<Append>,
+}
+
+impl SharedLog {
+    pub fn new(log: Log) -> Self {
+        let config: Config = log.config.clone();
+        let inner: Inner = Arc::new(RwLock::new(Some(log)));
+
+        let (appends, queue) = mpsc::channel();
+        let committer = inner.clone();
+        thread::spawn(move || commit_appends(committer, queue));
+
+        // Keeps the interval policy honest when appends stop arriving.
+        if let SyncPolicy::Interval(interval) = config.sync {
+            let inner = Arc::downgrade(&inner);
+            thread::spawn(move || {
+                run_periodically(inner, interval, "sync log", |log| {
+                    if log.unsynced > 0 && log.last_sync.elapsed() >= interval {
+                        log.sync()?;
+                    }
+                    Ok(())
+                })
+            });
+        }
+        let retention = &config.retention;
+        if retention.max_bytes.is_some() || retention.max_age.is_some() {
+            let inner = Arc::downgrade(&inner);
+            thread::spawn(move || {
+                run_periodically(
+                    inner,
+                    config.retention.check_interval,
+                    "enforce retention",
+                    Log::enforce_retention,
+                )
+            });
+        }
+        if config.compaction.enabled {
+            let inner = Arc::downgrade(&inner);
+            thread::spawn(move || {
+                run_in_phases(
+                    inner,
+                    config.compaction.check_interval,
+                    "compact log",
+                    Log::prepare_compaction,
+                    |compaction| compaction.run(),
+                    Log::finish_compaction,
+                )
+            });
+        }
+        if let Some(tiering) = &config.tiering {
+            let inner = Arc::downgrade(&inner);
+            let interval: Duration = tiering.check_interval;
+            thread::spawn(move || {
+                run_in_phases(
+                    inner,
+                    interval,
+                    "offload segments",
+                    Log::prepare_offload,
+                    |offload| offload.run(),
+                    Log::finish_offload,
+                )
+            });
+        }
+        Self { inner, appends }
+    }
+
+    // Returns the record's offset once the commit covering it has completed.
+    pub fn append(&self, record: Record) -> Result<u64, Error> {
+        let (offset, _) = self.append_batch(vec![record])?;
+        Ok(offset)
+    }
+
+    // Returns the first and last offset of the batch once the commit covering
+    // it has completed.
+    pub fn append_batch(&self, records: Vec<Record>) -> Result<(u64, u64), Error> {
+        let (reply, offsets) = mpsc::channel();
+        self.appends
+            .send(Append { records, reply })
+            .map_err(|_| Error::Closed)?;
+        offsets.recv().map_err(|_| Error::Closed)?
+    }
+
+    // Offloaded segments are fetched after the read lock is released, so a
+    // slow object store holds up no appends.
+    pub fn read(&self, offset: u64) -> Result<Record, Error> {
+        let progress = {
+            let log = self.inner.read().expect("log lock poisoned");
+            log.as_ref().ok_or(Error::Closed)?.read_local(offset)?
+        };
+        match progress {
+            Progress::Done(record) => Ok(record),
+            Progress::Fetch(fetch) => fetch.segment()?.read(offset),
+        }
+    }
+
+    pub fn read_range(
+        &self,
+        start: u64,
+        max_records: usize,
+        max_bytes: u64,
+    ) -> Result<(Vec<Record>, u64), Error> {
+        let mut page = Page::new(start, max_records, max_bytes);
+        loop {
+            let progress = {
+                let log = self.inner.read().expect("log lock poisoned");
+                log.as_ref().ok_or(Error::Closed)?.fill_local(&mut page)?
+            };
+            let fetch = match progress {
+                Progress::Done(next) => return Ok((page.records, next)),
+                Progress::Fetch(fetch) => fetch,
+            };
+            if let Some(next) = page.fill_offloaded(fetch)? {
+                return Ok((page.records, next));
+            }
+        }
+    }
+
+    // Offloaded segments are probed after the read lock is released.
+    pub fn offset_for_time(&self, timestamp: u64) -> Result<u64, Error> {
+        let search = {
+            let log = self.inner.read().expect("log lock poisoned");
+            log.as_ref().ok_or(Error::Closed)?.time_search(timestamp)
+        };
+        search.run()
+    }
+
+    // Only holds the read lock while the snapshot is taken, not while it is
+    // written out.
+    pub fn snapshot(&self, upto: u64) -> Result<Snapshot, Error> {
+        let log = self.inner.read().expect("log lock poisoned");
+        log.as_ref().ok_or(Error::Closed)?.snapshot(upto)
+    }
+
+    pub fn stats(&self) -> Result<Stats, Error> {
+        let log = self.inner.read().expect("log lock poisoned");
+        Ok(log.as_ref().ok_or(Error::Closed)?.stats())
+    }
+
+    pub fn syncs(&self) -> Result<u64, Error> {
+        let log = self.inner.read().expect("log lock poisoned");
+        Ok(log.as_ref().ok_or(Error::Closed)?.syncs())
+    }
+
+    pub fn unsynced(&self) -> Result<u64, Error> {
+        let log = self.inner.read().expect("log lock poisoned");
+        Ok(log.as_ref().ok_or(Error::Closed)?.unsynced())
+    }
+
+    pub fn lowest_offset(&self) -> Result<u64, Error> {
+        let log = self.inner.read().expect("log lock poisoned");
+        Ok(log.as_ref().ok_or(Error::Closed)?.lowest_offset())
+    }
+
+    pub fn highest_offset(&self) -> Result<Option<u64>, Error> {
+        let log = self.inner.read().expect("log lock poisoned");
+        Ok(log.as_ref().ok_or(Error::Closed)?.highest_offset())
+    }
+
+    pub fn truncate(&self, lowest: u64) -> Result<(), Error> {
+        let mut log = self.inner.write().expect("log lock poisoned");
+        log.as_mut().ok_or(Error::Closed)?.truncate(lowest)
+    }
+
+    pub fn sync(&self) -> Result<(), Error> {
+        let mut log = self.inner.write().expect("log lock poisoned");
+        log.as_mut().ok_or(Error::Closed)?.sync()
+    }
+
+    pub fn close(&self) -> Result<(), Error> {
+        let mut log = self.inner.write().expect("log lock poisoned");
+        log.take().ok_or(Error::Closed)?.close()
+    }
+}
+
+// Waits for an append, gathers whatever else has queued up behind it and
+// commits the group together. A record is only acknowledged once its group's
+// commit succeeds. The thread exits once every handle has been dropped.
+fn commit_appends(inner: Inner, queue: Receiver<Append>) {
+    while let Ok(first) = queue.recv() {
+        let mut group: Vec<Append> = vec![first];
+        group.extend(queue.try_iter().take(MAX_GROUP_COMMIT - 1));
+
+        let mut log = inner.write().expect("log lock poisoned");
+        let Some(log) = log.as_mut() else {
+            for append in group {
+                let _ = append.reply.send(Err(Error::Closed));
+            }
+            continue;
+        };
+
+        let mark = log.mark();
+        let written: Vec<(Reply, Offsets)> = group
+            .into_iter()
+            .map(|append| (append.reply, log.write(append.records)))
+            .collect();
+        let mut committed: Result<(), Error> = log.commit();
+        if committed.is_err() {
+            // The whole group is taken back, so none of it stays readable
+            // after its appends were told they failed.
+            if let Err(err) = log.rollback(mark) {
+                committed = Err(err);
+            }
+        }
+        for (reply, result) in written {
+            let result = match (&committed, result) {
+                (Err(err), Ok(_)) => {
+                    Err(Error::Io(io::Error::other(format!("commit failed: {err}"))))
+                }
+                (_, result) => result,
+            };
+            let _ = reply.send(result);
+        }
+    }
+}
+
+// Runs `task` against the log every `interval` until every handle has been
+// dropped or the log has been closed. Failures are reported and retried on
+// the next tick.
+fn run_periodically<F>(
+    inner: Weak<RwLock<Option<Log>>>,
+    interval: Duration,
+    name: &str,
+    mut task: F,
+) where
+    F: FnMut(&mut Log) -> Result<(), Error>,
+{
+    loop {
+        thread::sleep(interval);
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        let mut log = inner.write().expect("log lock poisoned");
+        let Some(log) = log.as_mut() else {
+            return;
+        };
+        if let Err(err) = task(log) {
+            eprintln!("failed to {name}: {err}");
+        }
+    }
+}
+
+// Runs a task against the log every `interval` in three phases, for work
+// too slow to do under the log's lock: `prepare` captures what the task needs
+// under the read lock, `run` does the work without any lock and `finish`
+// applies the outcome under the write lock. Appends and reads carry on while
+// the task runs. Stops like `run_periodically`.
+fn run_in_phases<P, R>(
+    inner: Weak<RwLock<Option<Log>>>,
+    interval: Duration,
+    name: &str,
+    prepare: impl Fn(&Log) -> Result<P, Error>,
+    run: impl Fn(P) -> Result<R, Error>,
+    finish: impl Fn(&mut Log, R) -> Result<(), Error>,
+) {
+    loop {
+        thread::sleep(interval);
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        let prepared = match inner.read().expect("log lock poisoned").as_ref() {
+            Some(log) => prepare(log),
+            None => return,
+        };
+        let outcome = match prepared.and_then(&run) {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                eprintln!("failed to {name}: {err}");
+                continue;
+            }
+        };
+        let mut log = inner.write().expect("log lock poisoned");
+        let Some(log) = log.as_mut() else {
+            return;
+        };
+        if let Err(err) = finish(log, outcome) {
+            eprintln!("failed to {name}: {err}");
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+
+use super::config::Config;
+use super::encryption::Keyring;
+use super::error::Error;
+use super::segment::Segment;
+use super::tiered::ObjectStore;
+
+// A snapshot is a tar archive holding a manifest followed by the store of
+// every segment it covers, oldest first. Indexes are left out since they are
+// rebuilt from the stores on restore.
+pub const FORMAT: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+
+// Offloaded segments are fetched into a subdirectory of this one, unique to
+// the snapshot, while they are written out. Spooled archives live here too.
+pub const SNAPSHOT_DIR: &str = "snapshot";
+
+static NEXT_STAGING: AtomicU64 = AtomicU64::new(0);
+
+// Describes the contents of a snapshot. It is the first entry of the archive
+// so a restore can check what it is given before unpacking any of it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u32,
+    // When the snapshot was taken, in milliseconds since the Unix epoch.
+    pub created: u64,
+    pub lowest_offset: u64,
+    // The offset appends resume at once the snapshot is restored.
+    pub next_offset: u64,
+    // Base offsets of the segments in the archive, in order.
+    pub segments: Vec<u64>,
+}
+
+// The part of a segment's store included in a snapshot.
+pub struct Entry {
+    pub base_offset: u64,
+    pub file: File,
+    pub len: u64,
+    pub next_offset: u64,
+}
+
+// A point-in-time view of the log, taken by `Log::snapshot`. It holds open
+// handles on the stores it covers and the length of each to include, so
+// appends can carry on while it is written out with `write_to`.
+pub struct Snapshot {
+    pub(super) dir: PathBuf,
+    pub(super) config: Config,
+    pub(super) keys: Arc<Keyring>,
+    pub(super) upto: u64,
+    pub(super) lowest_offset: u64,
+    // Offloaded segments covered by the snapshot, fetched while it is
+    // written out.
+    pub(super) offloaded: Vec<u64>,
+    pub(super) entries: Vec<Entry>,
+}
+
+impl Snapshot {
+    // Writes the snapshot to `writer` as an archive and returns its manifest.
+    pub fn write_to(self, writer: impl Write) -> Result<Manifest, Error> {
+        let staging: PathBuf = self
+            .dir
+            .join(SNAPSHOT_DIR)
+            .join(NEXT_STAGING.fetch_add(1, Ordering::Relaxed).to_string());
+        let result = self.write_archive(writer, &staging);
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        result
+    }
+
+    // Writes the snapshot to a file that is deleted as soon as it is
+    // created, and returns the archive mapped into memory along with its
+    // manifest. This hands a snapshot out whole, as an HTTP response body,
+    // without holding it in memory.
+    pub fn spool(self) -> Result<(Manifest, Bytes), Error> {
+        let spool_dir: PathBuf = self.dir.join(SNAPSHOT_DIR);
+        fs::create_dir_all(&spool_dir)?;
+        let path: PathBuf = spool_dir.join(format!(
+            "{}.tar",
+            NEXT_STAGING.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file: File = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        fs::remove_file(&path)?;
+        let manifest: Manifest = self.write_to(BufWriter::new(&file))?;
+        // Safety: nothing else can reach the file once it is deleted, so the
+        // mapped bytes never change.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok((manifest, Bytes::from_owner(mmap)))
+    }
+
+    fn write_archive(self, writer: impl Write, staging: &Path) -> Result<Manifest, Error> {
+        // The manifest needs the offset the snapshot ends at, so when it ends
+        // in an offloaded segment that segment is fetched up front.
+        let mut last: Option<(Segment, Entry)> = None;
+        if self.entries.is_empty() {
+            if let Some(&base_offset) = self.offloaded.last() {
+                last = Some(self.fetch(base_offset, staging)?);
+            }
+        }
+        let next_offset: u64 = match (self.entries.last(), &last) {
+            (Some(entry), _) | (None, Some((_, entry))) => entry.next_offset,
+            (None, None) => self.lowest_offset,
+        };
+        let mut segments: Vec<u64> = self.offloaded.clone();
+        segments.extend(self.entries.iter().map(|entry| entry.base_offset));
+        let manifest = Manifest {
+            format: FORMAT,
+            created: super::now_millis(),
+            lowest_offset: self.lowest_offset,
+            next_offset,
+            segments,
+        };
+
+        let mut archive = tar::Builder::new(writer);
+        let json: Vec<u8> = serde_json::to_vec_pretty(&manifest).map_err(io::Error::other)?;
+        append(&mut archive, MANIFEST, json.len() as u64, &json[..])?;
+        for &base_offset in &self.offloaded {
+            let (segment, entry) = match last.take() {
+                Some(fetched) if fetched.1.base_offset == base_offset => fetched,
+                _ => self.fetch(base_offset, staging)?,
+            };
+            append_entry(&mut archive, entry)?;
+            segment.remove()?;
+        }
+        for entry in self.entries {
+            append_entry(&mut archive, entry)?;
+        }
+        archive.into_inner()?.flush()?;
+        Ok(manifest)
+    }
+
+    fn fetch(&self, base_offset: u64, staging: &Path) -> Result<(Segment, Entry), Error> {
+        let objects: Arc<dyn ObjectStore> = self
+            .config
+            .tiering
+            .as_ref()
+            .map(|tiering| tiering.store.clone())
+            .expect("offloaded segments imply tiering");
+        let segment: Segment = Segment::fetch(
+            objects.as_ref(),
+            staging,
+            base_offset,
+            &self.config,
+            &self.keys,
+        )?;
+        let entry: Entry = segment.snapshot(self.upto)?;
+        Ok((segment, entry))
+    }
+}
+
+fn append_entry(archive: &mut tar::Builder<impl Write>, entry: Entry) -> Result<(), Error> {
+    let name = format!("{}.store", entry.base_offset);
+    append(archive, &name, entry.len, entry.file.take(entry.len))
+}
+
+fn append(
+    archive: &mut tar::Builder<impl Write>,
+    name: &str,
+    len: u64,
+    data: impl Read,
+) -> Result<(), Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(len);
+    header.set_mode(0o644);
+    header.set_mtime(super::now_millis() / 1000);
+    header.set_entry_type(tar::EntryType::Regular);
+    archive.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+// Unpacks the snapshot read from `reader` into `dir`, which must not exist
+// yet or be empty. The stores are unpacked aside and opened with `config`
+// to rebuild their indexes and check they hold exactly what the manifest
+// says before they are moved into place.
+pub fn restore(reader: impl Read, dir: &Path, config: &Config) -> Result<Manifest, Error> {
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        let message = format!("{} is not empty", dir.display());
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, message).into());
+    }
+    let name: &str = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| invalid(format!("{} is not a directory name", dir.display())))?;
+    let staging: PathBuf = dir.with_file_name(format!("{name}.restoring"));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+
+    let manifest: Manifest = match unpack(reader, &staging, config) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            fs::remove_dir_all(&staging)?;
+            return Err(err);
+        }
+    };
+    if dir.exists() {
+        fs::remove_dir(dir)?;
+    }
+    fs::rename(&staging, dir)?;
+    Ok(manifest)
+}
+
+fn unpack(reader: impl Read, staging: &Path, config: &Config) -> Result<Manifest, Error> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = archive.entries()?;
+
+    let manifest: Manifest = match entries.next() {
+        Some(entry) => {
+            let entry = entry?;
+            if entry.path()?.as_os_str() != MANIFEST {
+                return Err(invalid("snapshot does not start with a manifest"));
+            }
+            serde_json::from_reader(entry)
+                .map_err(|err| invalid(format!("invalid snapshot manifest: {err}")))?
+        }
+        None => return Err(invalid("snapshot is empty")),
+    };
+    if manifest.format != FORMAT {
+        return Err(invalid(format!(
+            "unsupported snapshot format {}, expected {FORMAT}",
+            manifest.format
+        )));
+    }
+
+    let mut segments = manifest.segments.iter();
+    for entry in entries {
+        let mut entry = entry?;
+        let expected: Option<String> = segments
+            .next()
+            .map(|base_offset| format!("{base_offset}.store"));
+        let path: PathBuf = entry.path()?.into_owned();
+        if expected.as_deref().map(Path::new) != Some(path.as_path()) {
+            return Err(invalid(format!(
+                "unexpected {} in snapshot",
+                path.display()
+            )));
+        }
+        let mut file = File::create(staging.join(&path))?;
+        io::copy(&mut entry, &mut file)?;
+        file.sync_all()?;
+    }
+    if segments.next().is_some() {
+        return Err(invalid("snapshot is missing segments"));
+    }
+
+    // Segments stay local until the restored log is opened for real.
+    let config = Config {
+        tiering: None,
+        ..config.clone()
+    };
+    let log = super::Log::open(staging, config)?;
+    let lowest_offset: u64 = log.lowest_offset();
+    let next_offset: u64 = log
+        .highest_offset()
+        .map_or(lowest_offset, |highest| highest + 1);
+    log.close()?;
+    if (lowest_offset, next_offset) != (manifest.lowest_offset, manifest.next_offset) {
+        return Err(invalid(format!(
+            "snapshot holds offsets {lowest_offset} to {next_offset}, but its manifest lists {} to {}",
+            manifest.lowest_offset, manifest.next_offset
+        )));
+    }
+    Ok(manifest)
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into()).into()
+}
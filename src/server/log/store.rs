@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use memmap2::Mmap;
+
+use super::error::Error;
+
+// Every record is framed by its length (big-endian u64) and the CRC32 of its
+// bytes (big-endian u32), followed by the bytes themselves.
+pub const LEN_WIDTH: u64 = 8;
+pub const CRC_WIDTH: u64 = 4;
+pub const FRAME_WIDTH: u64 = LEN_WIDTH + CRC_WIDTH;
+
+// A store begins with these magic bytes and the version of the format its
+// frames are written in (big-endian u32). Stores written before the header
+// was introduced start straight with their first frame and are read as
+// version 1, which otherwise shares the layout of version 2. Version 3 adds
+// a codec byte to the start of every frame and version 4 puts an encryption
+// key id in front of that.
+pub const MAGIC: [u8; 4] = *b"CHRN";
+pub const HEADER_WIDTH: u64 = MAGIC.len() as u64 + 4;
+pub const LEGACY_VERSION: u32 = 1;
+pub const COMPRESSION_VERSION: u32 = 3;
+pub const ENCRYPTION_VERSION: u32 = 4;
+pub const VERSION: u32 = 4;
+
+pub struct Store {
+    file: File,
+    version: u32,
+    // Position of the first frame, just past the header if there is one.
+    start: u64,
+    writer: Mutex<Writer>,
+    // Once a store is sealed it is mapped into memory and reads hand out
+    // slices of the map rather than copies of the file.
+    sealed: Option<Bytes>,
+}
+
+struct Writer {
+    buf: BufWriter<File>,
+    size: u64,
+}
+
+impl Store {
+    // Opens a store, writing the header if the store is new. Fails with
+    // `Error::UnsupportedVersion` if the store was written in a format this
+    // build does not know.
+    pub fn new(file: File) -> Result<Self, Error> {
+        let mut size: u64 = file.metadata()?.len();
+        let mut header = [0u8; HEADER_WIDTH as usize];
+        let (version, start) = if size >= HEADER_WIDTH {
+            file.read_exact_at(&mut header, 0)?;
+            if header[..MAGIC.len()] == MAGIC {
+                let version = u32::from_be_bytes(header[MAGIC.len()..].try_into().unwrap());
+                if !(LEGACY_VERSION..=VERSION).contains(&version) {
+                    return Err(Error::UnsupportedVersion { version });
+                }
+                (version, HEADER_WIDTH)
+            } else {
+                (LEGACY_VERSION, 0)
+            }
+        } else {
+            // Either a new store or one whose header was torn by a crash
+            // before any frame made it in.
+            header[..MAGIC.len()].copy_from_slice(&MAGIC);
+            header[MAGIC.len()..].copy_from_slice(&VERSION.to_be_bytes());
+            file.set_len(0)?;
+            (&file).write_all(&header)?;
+            size = HEADER_WIDTH;
+            (VERSION, HEADER_WIDTH)
+        };
+
+        let buf = BufWriter::new(file.try_clone()?);
+        Ok(Self {
+            file,
+            version,
+            start,
+            writer: Mutex::new(Writer { buf, size }),
+            sealed: None,
+        })
+    }
+
+    // The format version the store's frames are written in.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    // Position of the store's first frame.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    // Appends data to the store and returns the number of bytes written
+    // along with the position the record starts at.
+    pub fn append(&self, data: &[u8]) -> io::Result<(u64, u64)> {
+        let mut writer = self.writer.lock().expect("store lock poisoned");
+        let pos: u64 = writer.size;
+        writer.buf.write_all(&(data.len() as u64).to_be_bytes())?;
+        writer.buf.write_all(&crc32fast::hash(data).to_be_bytes())?;
+        writer.buf.write_all(data)?;
+        let written: u64 = FRAME_WIDTH + data.len() as u64;
+        writer.size += written;
+        Ok((written, pos))
+    }
+
+    // Reads the record at `pos`, failing with `Error::Corrupt` if its frame
+    // runs past the end of the store or its checksum does not match.
+    pub fn read(&self, pos: u64) -> Result<Bytes, Error> {
+        if let Some(sealed) = &self.sealed {
+            let start: usize = pos.min(sealed.len() as u64) as usize;
+            let header: &[u8] = sealed
+                .get(start..start + FRAME_WIDTH as usize)
+                .ok_or(Error::Corrupt { position: pos })?;
+            let len: u64 = frame_len(header, pos, sealed.len() as u64)?;
+            let data_start: usize = start + FRAME_WIDTH as usize;
+            let data: Bytes = sealed.slice(data_start..data_start + len as usize);
+            return verify(data, header, pos);
+        }
+
+        let size: u64 = {
+            let mut writer = self.writer.lock().expect("store lock poisoned");
+            writer.buf.flush()?;
+            writer.size
+        };
+        if pos + FRAME_WIDTH > size {
+            return Err(Error::Corrupt { position: pos });
+        }
+        let mut header = [0u8; FRAME_WIDTH as usize];
+        self.file.read_exact_at(&mut header, pos)?;
+        let len: u64 = frame_len(&header, pos, size)?;
+        let mut data = vec![0u8; len as usize];
+        self.file.read_exact_at(&mut data, pos + FRAME_WIDTH)?;
+        verify(Bytes::from(data), &header, pos)
+    }
+
+    // Maps the store into memory once nothing more will be appended to it.
+    pub fn seal(&mut self) -> io::Result<()> {
+        self.flush()?;
+        // Safety: sealed stores are never written to or truncated again, so
+        // the mapped bytes stay valid for as long as the map is alive. The one
+        // exception is `unseal`, which only undoes a seal made under the same
+        // exclusive borrow of the log, before any reader saw the map.
+        let mmap = unsafe { Mmap::map(&self.file)? };
+        self.sealed = Some(Bytes::from_owner(mmap));
+        Ok(())
+    }
+
+    // Reopens a sealed store for appends, as when the log takes back the
+    // roll that sealed it.
+    pub fn unseal(&mut self) {
+        self.sealed = None;
+    }
+
+    // Drops everything from `pos` onwards, used to cut off a record that was
+    // only partially written when the process died or to take back writes
+    // whose commit failed.
+    pub fn truncate(&self, pos: u64) -> io::Result<()> {
+        let mut writer = self.writer.lock().expect("store lock poisoned");
+        if writer.buf.flush().is_err() {
+            // Whatever could not be written is dropped rather than left to
+            // land after the cut once the disk recovers.
+            let file = writer.buf.get_ref().try_clone()?;
+            let unwritten = std::mem::replace(&mut writer.buf, BufWriter::new(file));
+            let _ = unwritten.into_parts();
+        }
+        self.file.set_len(pos)?;
+        writer.size = pos;
+        Ok(())
+    }
+
+    pub fn size(&self) -> u64 {
+        self.writer.lock().expect("store lock poisoned").size
+    }
+
+    // A read-only copy of the store that ends where the store ends now. It
+    // reads through its own handle on the file, so it stays readable after
+    // the store is replaced or deleted and never sees later appends.
+    pub fn view(&self) -> io::Result<Store> {
+        let mut writer = self.writer.lock().expect("store lock poisoned");
+        writer.buf.flush()?;
+        Ok(Self {
+            file: self.file.try_clone()?,
+            version: self.version,
+            start: self.start,
+            writer: Mutex::new(Writer {
+                buf: BufWriter::new(self.file.try_clone()?),
+                size: writer.size,
+            }),
+            sealed: self.sealed.clone(),
+        })
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.lock().expect("store lock poisoned").buf.flush()
+    }
+
+    // Flushes buffered writes and waits for them to reach the disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.flush()?;
+        self.file.sync_data()
+    }
+}
+
+// Returns the length of the record whose frame header starts at `pos`,
+// making sure the record fits within a store of `size` bytes.
+fn frame_len(header: &[u8], pos: u64, size: u64) -> Result<u64, Error> {
+    let len: u64 = u64::from_be_bytes(header[..LEN_WIDTH as usize].try_into().unwrap());
+    if len > size - pos - FRAME_WIDTH {
+        return Err(Error::Corrupt { position: pos });
+    }
+    Ok(len)
+}
+
+fn verify(data: Bytes, header: &[u8], pos: u64) -> Result<Bytes, Error> {
+    let crc: u32 = u32::from_be_bytes(header[LEN_WIDTH as usize..].try_into().unwrap());
+    if crc32fast::hash(&data) != crc {
+        return Err(Error::Corrupt { position: pos });
+    }
+    Ok(data)
+}
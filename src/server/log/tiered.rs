@@ -0,0 +1,74 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Somewhere closed segments can be offloaded to once they leave the hot
+// window, such as a bucket in a cloud object store. Objects are named after
+// the segment files they hold and are only ever written whole.
+pub trait ObjectStore: fmt::Debug + Send + Sync {
+    // Copies the file at `from` into the object `name`, replacing any object
+    // already there.
+    fn upload(&self, name: &str, from: &Path) -> io::Result<()>;
+
+    // Copies the object `name` into a new file at `to`.
+    fn download(&self, name: &str, to: &Path) -> io::Result<()>;
+
+    // Deletes the object `name`, succeeding if there is no such object.
+    fn delete(&self, name: &str) -> io::Result<()>;
+
+    // Names every object in the store.
+    fn list(&self) -> io::Result<Vec<String>>;
+}
+
+// An object store kept in a directory on the local filesystem, such as a
+// mounted network share or a temporary directory in tests.
+#[derive(Debug)]
+pub struct LocalObjectStore {
+    dir: PathBuf,
+}
+
+impl LocalObjectStore {
+    pub fn new(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir: PathBuf = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+}
+
+// Uploads land under a temporary name first so an object is never seen half
+// written.
+const PARTIAL_SUFFIX: &str = ".partial";
+
+impl ObjectStore for LocalObjectStore {
+    fn upload(&self, name: &str, from: &Path) -> io::Result<()> {
+        let partial: PathBuf = self.dir.join(format!("{name}{PARTIAL_SUFFIX}"));
+        fs::copy(from, &partial)?;
+        fs::File::open(&partial)?.sync_all()?;
+        fs::rename(&partial, self.dir.join(name))
+    }
+
+    fn download(&self, name: &str, to: &Path) -> io::Result<()> {
+        fs::copy(self.dir.join(name), to)?;
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> io::Result<()> {
+        match fs::remove_file(self.dir.join(name)) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        let mut names: Vec<String> = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            if let Ok(name) = entry?.file_name().into_string() {
+                if !name.ends_with(PARTIAL_SUFFIX) {
+                    names.push(name);
+                }
+            }
+        }
+        Ok(names)
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+// Each entry is an append timestamp in milliseconds since the Unix epoch
+// (big-endian u64) followed by the offset, relative to the segment's base
+// offset, of the first record stamped with it (big-endian u32).
+const TS_WIDTH: u64 = 8;
+const OFF_WIDTH: u64 = 4;
+pub const TIME_ENT_WIDTH: u64 = TS_WIDTH + OFF_WIDTH;
+
+// A sparse index from append time to offset. Only the first record stamped
+// with each new timestamp gets an entry, and since timestamps never go
+// backwards the entries are sorted by both timestamp and offset.
+pub struct TimeIndex {
+    file: File,
+    entries: Vec<(u64, u32)>,
+}
+
+impl TimeIndex {
+    pub fn new(mut file: File) -> io::Result<Self> {
+        let mut data: Vec<u8> = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let index = Self {
+            file,
+            entries: parse(&data),
+        };
+        if index.size() < data.len() as u64 {
+            index.file.set_len(index.size())?;
+        }
+        Ok(index)
+    }
+
+    // Records that `off` is the first record stamped `timestamp`. Timestamps
+    // no later than the last entry are already covered and ignored.
+    pub fn write(&mut self, timestamp: u64, off: u32) -> io::Result<()> {
+        if self.last().is_some_and(|(last_ts, _)| timestamp <= last_ts) {
+            return Ok(());
+        }
+        let mut entry = [0u8; TIME_ENT_WIDTH as usize];
+        entry[..TS_WIDTH as usize].copy_from_slice(&timestamp.to_be_bytes());
+        entry[TS_WIDTH as usize..].copy_from_slice(&off.to_be_bytes());
+        if let Err(err) = self.file.write_all(&entry) {
+            // Cuts off whatever part of the entry made it in.
+            let _ = self.file.set_len(self.size());
+            return Err(err);
+        }
+        self.entries.push((timestamp, off));
+        Ok(())
+    }
+
+    pub fn first(&self) -> Option<(u64, u32)> {
+        self.entries.first().copied()
+    }
+
+    pub fn last(&self) -> Option<(u64, u32)> {
+        self.entries.last().copied()
+    }
+
+    // Returns the relative offset of the first record stamped at or after
+    // `timestamp`, if the segment has one.
+    pub fn lookup(&self, timestamp: u64) -> Option<u32> {
+        let index: usize = self.entries.partition_point(|&(ts, _)| ts < timestamp);
+        self.entries.get(index).map(|&(_, off)| off)
+    }
+
+    // Drops the entries for records at `off` and beyond.
+    pub fn truncate(&mut self, off: u32) -> io::Result<()> {
+        let kept: usize = self.entries.partition_point(|&(_, entry)| entry < off);
+        if kept < self.entries.len() {
+            self.entries.truncate(kept);
+            self.file.set_len(self.size())?;
+        }
+        Ok(())
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    fn size(&self) -> u64 {
+        self.entries.len() as u64 * TIME_ENT_WIDTH
+    }
+}
+
+// Decodes the entries in the bytes of a time index file. A crash can leave a
+// torn entry at the end of the file; the entries stop where they stop
+// increasing.
+pub fn parse(data: &[u8]) -> Vec<(u64, u32)> {
+    let mut entries: Vec<(u64, u32)> = Vec::new();
+    for entry in data.chunks_exact(TIME_ENT_WIDTH as usize) {
+        let timestamp = u64::from_be_bytes(entry[..TS_WIDTH as usize].try_into().unwrap());
+        let off = u32::from_be_bytes(entry[TS_WIDTH as usize..].try_into().unwrap());
+        if entries
+            .last()
+            .is_some_and(|&(last_ts, last_off)| timestamp <= last_ts || off <= last_off)
+        {
+            break;
+        }
+        entries.push((timestamp, off));
+    }
+    entries
+}
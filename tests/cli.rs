@@ -0,0 +1,283 @@
+use std::fs;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Child, Command, Output, Stdio};
+
+use bytes::Bytes;
+use chronicle::server::log::store::{FRAME_WIDTH, HEADER_WIDTH};
+use chronicle::server::log::{Config, Log, Record};
+use serde_json::Value;
+use tempfile::TempDir;
+
+mod common;
+
+use common::{record, wait_until};
+
+// Runs the `chronicle` binary against the log in `dir`, with none of the
+// environment's settings.
+fn chronicle(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_chronicle"));
+    command.env_clear().arg("--dir").arg(dir).args(args);
+    command
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    chronicle(dir, args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+// Appends records 0 to 4 to a new log in `dir`.
+fn fill(dir: &Path) {
+    let mut log = Log::open(dir, Config::default()).unwrap();
+    for i in 0..5 {
+        log.append(record(i)).unwrap();
+    }
+    log.close().unwrap();
+}
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+// A server run by the binary, killed once dropped.
+struct Server(Child);
+
+impl Server {
+    fn start(dir: &Path, addr: SocketAddr, args: &[&str]) -> Self {
+        let child = chronicle(dir, args)
+            .args(["--listen", &addr.to_string(), "serve"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Self(child);
+        wait_until("the server listens", || TcpStream::connect(addr).is_ok());
+        server
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn snapshots_are_taken_by_the_running_server() {
+    let (dir, backup) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    fill(dir.path());
+    let addr = free_addr();
+    let _server = Server::start(dir.path(), addr, &[]);
+
+    let archive = backup.path().join("backup.tar");
+    let listen = addr.to_string();
+    let output = run(
+        dir.path(),
+        &[
+            "--listen",
+            &listen,
+            "snapshot",
+            "--upto",
+            "2",
+            "-o",
+            archive.to_str().unwrap(),
+        ],
+    );
+    stdout(&output);
+    assert!(
+        stderr(&output).contains("wrote 1 segments from offset 0 up to 3"),
+        "{output:?}"
+    );
+
+    let restored = backup.path().join("restored");
+    let output = run(&restored, &["restore", "-i", archive.to_str().unwrap()]);
+    stdout(&output);
+    assert!(
+        stderr(&output).contains("resuming at offset 3"),
+        "{output:?}"
+    );
+    let log = Log::open(&restored, Config::default()).unwrap();
+    assert_eq!(log.highest_offset(), Some(2));
+    assert_eq!(log.read(2).unwrap().value.unwrap(), "record-2");
+
+    // Without `-o` the whole log goes to stdout.
+    let output = run(dir.path(), &["--listen", &listen, "snapshot"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(output
+        .stdout
+        .windows(13)
+        .any(|name| name == b"manifest.json"));
+    assert!(stderr(&output).contains("up to 5"), "{output:?}");
+
+    // Only the server holds the log open.
+    let output = run(dir.path(), &["repair"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("already open"), "{output:?}");
+    let output = run(dir.path(), &["--listen", &free_addr().to_string(), "serve"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("already open"), "{output:?}");
+}
+
+#[test]
+fn large_snapshots_are_downloaded_whole() {
+    let (dir, backup) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    // Values that do not compress, so the archive spans megabytes.
+    let mut state: u64 = 1;
+    let values: Vec<Bytes> = (0..64)
+        .map(|_| {
+            (0..64 * 1024)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (state >> 56) as u8
+                })
+                .collect::<Vec<u8>>()
+                .into()
+        })
+        .collect();
+    let mut log = Log::open(dir.path(), Config::default()).unwrap();
+    for value in &values {
+        log.append(Record {
+            value: Some(value.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+    }
+    log.close().unwrap();
+    let addr = free_addr();
+    let _server = Server::start(dir.path(), addr, &[]);
+
+    let archive = backup.path().join("backup.tar");
+    let output = run(
+        dir.path(),
+        &[
+            "--listen",
+            &addr.to_string(),
+            "snapshot",
+            "-o",
+            archive.to_str().unwrap(),
+        ],
+    );
+    stdout(&output);
+    assert!(fs::metadata(&archive).unwrap().len() > 4 << 20);
+
+    let restored = backup.path().join("restored");
+    stdout(&run(
+        &restored,
+        &["restore", "-i", archive.to_str().unwrap()],
+    ));
+    let log = Log::open(&restored, Config::default()).unwrap();
+    assert_eq!(log.highest_offset(), Some(63));
+    for (offset, value) in values.iter().enumerate() {
+        assert_eq!(log.read(offset as u64).unwrap().value.as_ref(), Some(value));
+    }
+}
+
+#[test]
+fn snapshots_are_taken_over_tls() {
+    let (dir, certs) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    fill(dir.path());
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let (cert_path, key_path) = (certs.path().join("cert.pem"), certs.path().join("key.pem"));
+    fs::write(&cert_path, cert.cert.pem()).unwrap();
+    fs::write(&key_path, cert.signing_key.serialize_pem()).unwrap();
+    let cert_path = format!("tls.cert_path={}", cert_path.display());
+    let key_path = format!("tls.key_path={}", key_path.display());
+    let tls = ["--set", &cert_path, "--set", &key_path];
+    let addr = free_addr();
+    let _server = Server::start(dir.path(), addr, &tls);
+
+    let listen = addr.to_string();
+    let mut args = tls.to_vec();
+    args.extend(["--listen", &listen, "snapshot", "--upto", "0"]);
+    let output = run(dir.path(), &args);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stderr(&output).contains("up to 1"), "{output:?}");
+
+    // The certificate is only trusted for the name it was issued for.
+    args.extend(["--server-name", "example.com"]);
+    assert!(!run(dir.path(), &args).status.success());
+}
+
+#[test]
+fn snapshot_reports_server_errors() {
+    let dir = TempDir::new().unwrap();
+    let addr = free_addr();
+    let listen = addr.to_string();
+    let output = run(dir.path(), &["--listen", &listen, "snapshot"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("failed to connect"), "{output:?}");
+
+    let mut config = Config::default();
+    config.segment.initial_offset = 5;
+    let mut log = Log::open(dir.path(), config).unwrap();
+    log.append(record(5)).unwrap();
+    log.close().unwrap();
+    let _server = Server::start(dir.path(), addr, &[]);
+    let output = run(
+        dir.path(),
+        &["--listen", &listen, "snapshot", "--upto", "0"],
+    );
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("410"), "{output:?}");
+}
+
+#[test]
+fn inspects_a_stopped_log() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path());
+
+    let listing = stdout(&run(dir.path(), &["dump"]));
+    assert!(
+        listing.contains("segment 0: offsets 0 to 4, 5 records in 5 batches"),
+        "{listing}"
+    );
+    let records: Vec<Value> = stdout(&run(dir.path(), &["dump", "--from", "1", "--to", "2"]))
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let offsets: Vec<u64> = records
+        .iter()
+        .map(|record| record["offset"].as_u64().unwrap())
+        .collect();
+    assert_eq!(offsets, [1, 2]);
+    assert_eq!(stdout(&run(dir.path(), &["verify"])), "segment 0: ok\n");
+    assert_eq!(stdout(&run(dir.path(), &["repair"])), "nothing to repair\n");
+
+    let printed = stdout(&run(dir.path(), &["config", "print"]));
+    let config: toml::Value = toml::from_str(&printed).unwrap();
+    assert_eq!(config["data_dir"].as_str(), dir.path().to_str());
+}
+
+#[test]
+fn repair_needs_force_to_drop_readable_records() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path());
+    let path = dir.path().join("0.store");
+    let mut store = fs::read(&path).unwrap();
+    store[(HEADER_WIDTH + FRAME_WIDTH) as usize] ^= 0xff;
+    fs::write(&path, store).unwrap();
+
+    let output = run(dir.path(), &["repair"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("would drop"),
+        "{output:?}"
+    );
+    assert!(stderr(&output).contains("--force"), "{output:?}");
+
+    let repaired = stdout(&run(dir.path(), &["repair", "--force"]));
+    assert!(repaired.contains("offsets 0 to 4"), "{repaired}");
+    assert_eq!(stdout(&run(dir.path(), &["verify"])), "segment 0: ok\n");
+}
@@ -0,0 +1,42 @@
+// Fixtures shared by the integration tests. Each test binary only uses some
+// of them.
+#![allow(dead_code)]
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use chronicle::server::log::index::ENT_WIDTH;
+use chronicle::server::log::{Config, Record};
+
+// Three records per segment, so ten records span four segments.
+pub fn config() -> Config {
+    let mut config = Config::default();
+    config.segment.max_index_bytes = 3 * ENT_WIDTH;
+    config
+}
+
+pub fn record(i: u64) -> Record {
+    Record {
+        value: Some(Bytes::from(format!("record-{i}"))),
+        ..Default::default()
+    }
+}
+
+pub fn keyed(key: &str, value: Option<&str>) -> Record {
+    Record {
+        value: value.map(|value| Bytes::copy_from_slice(value.as_bytes())),
+        key: Some(Bytes::copy_from_slice(key.as_bytes())),
+        ..Default::default()
+    }
+}
+
+// Polls until `done` holds, failing the test with `what` if it still does
+// not after a few seconds. Background tasks get that long to catch up.
+pub fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting until {what}");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use chronicle::server::log::{Error, Log, Record, SharedLog};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{config, keyed, wait_until};
+
+fn value(log: &Log, offset: u64) -> Bytes {
+    log.read(offset).unwrap().value.unwrap()
+}
+
+#[test]
+fn keeps_only_the_latest_record_per_key() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    // Segments: [0 a1, 1 b1, 2 a2] [3 unkeyed, 4 b2, 5 a3] [6 c1]
+    log.append(keyed("a", Some("a1"))).unwrap();
+    log.append(keyed("b", Some("b1"))).unwrap();
+    log.append(keyed("a", Some("a2"))).unwrap();
+    log.append(Record {
+        value: Some(Bytes::from_static(b"unkeyed")),
+        ..Default::default()
+    })
+    .unwrap();
+    log.append(keyed("b", Some("b2"))).unwrap();
+    log.append(keyed("a", Some("a3"))).unwrap();
+    log.append(keyed("c", Some("c1"))).unwrap();
+
+    log.compact().unwrap();
+
+    for offset in [0, 1, 2] {
+        assert!(matches!(log.read(offset), Err(Error::Compacted { offset: o }) if o == offset));
+    }
+    assert_eq!(value(&log, 3), b"unkeyed"[..]);
+    assert_eq!(value(&log, 4), b"b2"[..]);
+    assert_eq!(value(&log, 5), b"a3"[..]);
+    assert_eq!(value(&log, 6), b"c1"[..]);
+
+    // Paging skips the gaps and appends carry on from the same offset.
+    let (records, next) = log.read_range(0, 10, u64::MAX).unwrap();
+    let offsets: Vec<u64> = records.iter().map(|record| record.offset).collect();
+    assert_eq!(offsets, [3, 4, 5, 6]);
+    assert_eq!(next, 7);
+    assert_eq!(log.append(keyed("c", Some("c2"))).unwrap(), 7);
+    log.close().unwrap();
+
+    let log = Log::open(dir.path(), config()).unwrap();
+    assert!(matches!(log.read(1), Err(Error::Compacted { .. })));
+    assert_eq!(value(&log, 4), b"b2"[..]);
+    assert_eq!(log.offset_for_time(0).unwrap(), 3);
+}
+
+#[test]
+fn tombstones_are_dropped_after_their_retention() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config();
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    // Segments: [0 a1, 1 a tombstone, 2 b1] [3 b2]
+    log.append(keyed("a", Some("a1"))).unwrap();
+    log.append(keyed("a", None)).unwrap();
+    log.append(keyed("b", Some("b1"))).unwrap();
+    log.append(keyed("b", Some("b2"))).unwrap();
+
+    // The tombstone outlives the value it deleted.
+    log.compact().unwrap();
+    assert!(matches!(log.read(0), Err(Error::Compacted { .. })));
+    assert_eq!(log.read(1).unwrap().value, None);
+    log.close().unwrap();
+
+    config.compaction.tombstone_retention = Duration::ZERO;
+    let mut log = Log::open(dir.path(), config).unwrap();
+    log.compact().unwrap();
+    for offset in [0, 1, 2] {
+        assert!(matches!(log.read(offset), Err(Error::Compacted { .. })));
+    }
+    assert_eq!(value(&log, 3), b"b2"[..]);
+    let (records, next) = log.read_range(0, 10, u64::MAX).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(next, 4);
+}
+
+#[test]
+fn compaction_runs_in_the_background() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config();
+    config.compaction.enabled = true;
+    config.compaction.check_interval = Duration::from_millis(10);
+    let mut log = Log::open(dir.path(), config).unwrap();
+    for i in 0..4 {
+        log.append(keyed("a", Some(&i.to_string()))).unwrap();
+    }
+    let log = SharedLog::new(log);
+
+    wait_until("compaction drops the old values", || {
+        matches!(log.read(0), Err(Error::Compacted { .. }))
+    });
+    assert_eq!(log.read(3).unwrap().value.unwrap(), b"3"[..]);
+}
@@ -0,0 +1,124 @@
+use bytes::Bytes;
+use chronicle::server::log::index::ENT_WIDTH;
+use chronicle::server::log::{Compression, Config, Log, Record};
+use tempfile::TempDir;
+
+fn config(compression: Compression) -> Config {
+    Config {
+        compression,
+        ..Default::default()
+    }
+}
+
+// Verbose JSON, like the events we store, compresses well.
+fn event(i: u64) -> Record {
+    let value = format!(
+        r#"{{"id":{i},"type":"page_view","user":"user-{}","path":"/articles/{i}","referrer":"https://example.com/"}}"#,
+        i % 7
+    );
+    Record {
+        value: Some(Bytes::from(value)),
+        ..Default::default()
+    }
+}
+
+fn append_events(log: &mut Log, from: u64) {
+    let batch: Vec<Record> = (from..from + 50).map(event).collect();
+    log.append_batch(batch).unwrap();
+}
+
+#[test]
+fn every_codec_round_trips_and_saves_space() {
+    for compression in [Compression::Zstd(3), Compression::Lz4, Compression::Snappy] {
+        let dir = TempDir::new().unwrap();
+        let mut log = Log::open(dir.path(), config(compression.clone())).unwrap();
+        append_events(&mut log, 0);
+
+        let stats = log.stats();
+        assert!(
+            stats.stored_bytes * 2 < stats.raw_bytes,
+            "{compression:?}: {stats:?}"
+        );
+        assert_eq!(log.read(7).unwrap().value, event(7).value);
+        log.close().unwrap();
+
+        let log = Log::open(dir.path(), config(compression.clone())).unwrap();
+        assert_eq!(log.stats(), stats, "{compression:?}");
+        let (records, _) = log.read_range(0, 100, u64::MAX).unwrap();
+        assert_eq!(records.len(), 50);
+        assert_eq!(records[49].value, event(49).value);
+    }
+}
+
+#[test]
+fn codec_can_change_between_runs() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), config(Compression::Zstd(3))).unwrap();
+    append_events(&mut log, 0);
+    log.close().unwrap();
+
+    let mut log = Log::open(dir.path(), config(Compression::None)).unwrap();
+    let before = log.stats();
+    append_events(&mut log, 50);
+    // Only the codec byte is added.
+    let after = log.stats();
+    assert_eq!(
+        after.stored_bytes - before.stored_bytes,
+        after.raw_bytes - before.raw_bytes + 1
+    );
+    log.close().unwrap();
+
+    let log = Log::open(dir.path(), config(Compression::Snappy)).unwrap();
+    for i in [0, 49, 50, 99] {
+        assert_eq!(log.read(i).unwrap().value, event(i).value);
+    }
+}
+
+#[test]
+fn incompressible_batches_grow_by_at_most_their_framing() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), config(Compression::Lz4)).unwrap();
+    let mut state: u32 = 1;
+    let noise: Vec<u8> = (0..1024)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        })
+        .collect();
+    log.append(Record {
+        value: Some(Bytes::from(noise.clone())),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let stats = log.stats();
+    // A batch that does not shrink is stored as is, behind its codec byte.
+    assert!(stats.stored_bytes <= stats.raw_bytes + 1, "{stats:?}");
+    assert_eq!(log.read(0).unwrap().value.unwrap(), noise);
+}
+
+#[test]
+fn stats_carry_over_across_runs() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config(Compression::Zstd(3));
+    config.segment.max_index_bytes = 60 * ENT_WIDTH;
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    for from in [0, 50, 100] {
+        append_events(&mut log, from);
+    }
+    let stats = log.stats();
+    log.close().unwrap();
+
+    // The stats of the segments before the active one are saved when the log
+    // rolls, and the active segment is counted on open.
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    assert_eq!(log.stats(), stats);
+    append_events(&mut log, 150);
+    let stats = log.stats();
+    log.close().unwrap();
+
+    // Without the saved stats every segment is counted.
+    std::fs::remove_file(dir.path().join("stats.json")).unwrap();
+    let log = Log::open(dir.path(), config).unwrap();
+    assert_eq!(log.stats(), stats);
+}
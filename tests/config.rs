@@ -0,0 +1,196 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chronicle::server::config::{ConfigError, ServerConfig, TlsConfig};
+use chronicle::server::http;
+use chronicle::server::log::index::ENT_WIDTH;
+use chronicle::server::log::{Compression, Log, SharedLog, SyncPolicy};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|&(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn write_file(dir: &TempDir, contents: &str) -> PathBuf {
+    let path = dir.path().join("chronicle.toml");
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn later_layers_override_earlier_ones() {
+    let dir = TempDir::new().unwrap();
+    let file = write_file(
+        &dir,
+        r#"
+            data_dir = "/var/lib/chronicle"
+            sync = "records:100"
+
+            [segment]
+            max_store_bytes = 4096
+            max_index_bytes = 120
+
+            [retention]
+            max_age = "7days"
+        "#,
+    );
+    let env = vars(&[
+        ("CHRONICLE_SYNC", "interval:1s"),
+        ("CHRONICLE_SEGMENT_MAX_STORE_BYTES", "8192"),
+        ("CHRONICLE_LISTEN", "127.0.0.1:9000, [::1]:9000"),
+        ("CHRONICLE_CONFIG", "ignored.toml"),
+        ("HOME", "/root"),
+    ]);
+    let overrides = vars(&[("segment.max_store_bytes", "16384")]);
+    let config = ServerConfig::load(Some(&file), env, &overrides).unwrap();
+
+    assert_eq!(config.data_dir, PathBuf::from("/var/lib/chronicle"));
+    assert_eq!(config.sync, SyncPolicy::Interval(Duration::from_secs(1)));
+    assert_eq!(config.segment.max_store_bytes, 16384);
+    assert_eq!(config.segment.max_index_bytes, 120);
+    assert_eq!(
+        config.retention.max_age,
+        Some(Duration::from_secs(7 * 24 * 60 * 60))
+    );
+    let listen: Vec<SocketAddr> = vec![
+        "127.0.0.1:9000".parse().unwrap(),
+        "[::1]:9000".parse().unwrap(),
+    ];
+    assert_eq!(config.listen, listen);
+    // Untouched settings keep their defaults.
+    assert_eq!(config.compression, Compression::None);
+    assert_eq!(
+        config.segment.initial_offset,
+        ServerConfig::default().segment.initial_offset
+    );
+
+    // The printed config reads back to the same settings.
+    let printed = write_file(&dir, &toml::to_string(&config).unwrap());
+    assert_eq!(ServerConfig::load(Some(&printed), [], &[]).unwrap(), config);
+}
+
+#[test]
+fn invalid_settings_are_reported_together() {
+    let overrides = vars(&[
+        ("segment.max_index_bytes", "13"),
+        ("sync", "records:0"),
+        ("compression", "zstd:99"),
+        ("tls.cert_path", "/nonexistent/cert.pem"),
+        ("tls.key_path", "/nonexistent/key.pem"),
+    ]);
+    match ServerConfig::load(None, [], &overrides) {
+        Err(ConfigError::Invalid(problems)) => assert_eq!(problems.len(), 5, "{problems:?}"),
+        other => panic!("expected invalid settings, got {other:?}"),
+    }
+
+    let too_big = (u32::MAX as u64 + 1) * ENT_WIDTH;
+    let overrides = vars(&[("segment.max_index_bytes", &too_big.to_string())]);
+    match ServerConfig::load(None, [], &overrides) {
+        Err(ConfigError::Invalid(problems)) => {
+            assert_eq!(problems.len(), 1, "{problems:?}");
+            assert!(problems[0].contains("at most"), "{problems:?}");
+        }
+        other => panic!("expected invalid settings, got {other:?}"),
+    }
+
+    // Unknown variables are only warned about, but unknown keys given
+    // explicitly are errors.
+    let config = ServerConfig::load(None, vars(&[("CHRONICLE_SEGMENTS", "1")]), &[]).unwrap();
+    assert_eq!(config, ServerConfig::default());
+    let unknown = ServerConfig::load(None, [], &vars(&[("segments", "1")]));
+    assert!(
+        matches!(unknown, Err(ConfigError::Parse { origin, .. }) if origin == "--set segments")
+    );
+
+    let dir = TempDir::new().unwrap();
+    let file = write_file(&dir, "[segment]\nmax_store_byte = 1\n");
+    let err = ServerConfig::load(Some(&file), [], &[]).unwrap_err();
+    assert!(err.to_string().contains("max_store_byte"), "{err}");
+
+    let err = ServerConfig::load(None, [], &vars(&[("sync", "sometimes")])).unwrap_err();
+    assert!(err.to_string().contains("in `sync`"), "{err}");
+
+    let err = ServerConfig::load(
+        None,
+        vars(&[("CHRONICLE_SEGMENT_MAX_STORE_BYTES", "big")]),
+        &[],
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("expected an integer"), "{err}");
+}
+
+#[tokio::test]
+async fn serves_over_tls() {
+    let dir = TempDir::new().unwrap();
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let tls = TlsConfig {
+        cert_path: dir.path().join("cert.pem"),
+        key_path: dir.path().join("key.pem"),
+    };
+    fs::write(&tls.cert_path, cert.cert.pem()).unwrap();
+    fs::write(&tls.key_path, cert.signing_key.serialize_pem()).unwrap();
+
+    let overrides = vars(&[
+        ("data_dir", dir.path().join("data").to_str().unwrap()),
+        ("tls.cert_path", tls.cert_path.to_str().unwrap()),
+        ("tls.key_path", tls.key_path.to_str().unwrap()),
+    ]);
+    let config = ServerConfig::load(None, [], &overrides).unwrap();
+    let acceptor = config.tls.as_ref().unwrap().acceptor().unwrap();
+    let log = SharedLog::new(Log::open(&config.data_dir, config.log_config().unwrap()).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(http::serve(vec![listener], log, Some(acceptor), async {
+        let _ = stopped.await;
+    }));
+
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(CertificateDer::from(cert.cert.der().to_vec()))
+        .unwrap();
+    let client = ClientConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::from(Arc::new(client))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET /admin/offsets HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+    // Plain HTTP is not served on a TLS listener.
+    let mut plain = TcpStream::connect(addr).await.unwrap();
+    plain
+        .write_all(b"GET /admin/offsets HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    let _ = plain.read_to_end(&mut response).await;
+    assert!(!response.starts_with(b"HTTP/1.1 200"));
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
@@ -0,0 +1,129 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use chronicle::server::log::index::ENT_WIDTH;
+use chronicle::server::log::{Config, EncryptionConfig, Error, Log, Record};
+use tempfile::TempDir;
+
+const SECRET: &[u8] = b"alice@example.com";
+
+// Three records per segment.
+fn config(key_file: &Path, active_key_id: u32) -> Config {
+    let mut config = Config::default();
+    config.segment.max_index_bytes = 3 * ENT_WIDTH;
+    config.encryption = Some(EncryptionConfig {
+        key_file: key_file.to_path_buf(),
+        active_key_id,
+    });
+    config
+}
+
+fn write_keys(dir: &Path, keys: &[(u32, u8)]) -> PathBuf {
+    let path = dir.join("keys");
+    let mut contents = String::from("# id key\n");
+    for (key_id, fill) in keys {
+        contents += &format!("{key_id} {}\n", STANDARD.encode([*fill; 32]));
+    }
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn record() -> Record {
+    Record {
+        value: Some(Bytes::from_static(SECRET)),
+        ..Default::default()
+    }
+}
+
+fn stores_contain_secret(dir: &Path) -> bool {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "store"))
+        .any(|path| {
+            let data = fs::read(path).unwrap();
+            data.windows(SECRET.len()).any(|window| window == SECRET)
+        })
+}
+
+#[test]
+fn batches_are_encrypted_at_rest() {
+    let keys = TempDir::new().unwrap();
+    let dir = TempDir::new().unwrap();
+    let key_file = write_keys(keys.path(), &[(1, 0xa1)]);
+
+    let mut log = Log::open(dir.path(), config(&key_file, 1)).unwrap();
+    for _ in 0..4 {
+        log.append(record()).unwrap();
+    }
+    log.close().unwrap();
+    assert!(!stores_contain_secret(dir.path()));
+
+    let log = Log::open(dir.path(), config(&key_file, 1)).unwrap();
+    assert_eq!(log.read(3).unwrap().value.unwrap(), SECRET);
+
+    // Without encryption the same data lands in the clear.
+    let plain = TempDir::new().unwrap();
+    let mut log = Log::open(plain.path(), Config::default()).unwrap();
+    log.append(record()).unwrap();
+    log.close().unwrap();
+    assert!(stores_contain_secret(plain.path()));
+}
+
+#[test]
+fn rotated_keys_keep_old_segments_readable() {
+    let keys = TempDir::new().unwrap();
+    let dir = TempDir::new().unwrap();
+    let key_file = write_keys(keys.path(), &[(1, 0xa1)]);
+    let mut log = Log::open(dir.path(), config(&key_file, 1)).unwrap();
+    for _ in 0..3 {
+        log.append(record()).unwrap();
+    }
+    log.close().unwrap();
+
+    let key_file = write_keys(keys.path(), &[(1, 0xa1), (2, 0xb2)]);
+    let mut log = Log::open(dir.path(), config(&key_file, 2)).unwrap();
+    for _ in 0..3 {
+        log.append(record()).unwrap();
+    }
+    for offset in 0..6 {
+        assert_eq!(log.read(offset).unwrap().value.unwrap(), SECRET);
+    }
+    log.close().unwrap();
+
+    // Dropping the old key while its segment is still around is refused.
+    let key_file = write_keys(keys.path(), &[(2, 0xb2)]);
+    assert!(matches!(
+        Log::open(dir.path(), config(&key_file, 2)),
+        Err(Error::MissingKey { key_id: 1 })
+    ));
+}
+
+#[test]
+fn missing_and_wrong_keys_are_reported() {
+    let keys = TempDir::new().unwrap();
+    let dir = TempDir::new().unwrap();
+    let key_file = write_keys(keys.path(), &[(1, 0xa1)]);
+    let mut log = Log::open(dir.path(), config(&key_file, 1)).unwrap();
+    log.append(record()).unwrap();
+    log.close().unwrap();
+
+    // The active key must be in the key file.
+    assert!(matches!(
+        Log::open(dir.path(), config(&key_file, 7)),
+        Err(Error::MissingKey { key_id: 7 })
+    ));
+    assert!(matches!(
+        Log::open(dir.path(), Config::default()),
+        Err(Error::MissingKey { key_id: 1 })
+    ));
+
+    let key_file = write_keys(keys.path(), &[(1, 0xff)]);
+    assert!(matches!(
+        Log::open(dir.path(), config(&key_file, 1)),
+        Err(Error::Decryption { key_id: 1, .. })
+    ));
+}
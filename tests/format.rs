@@ -0,0 +1,177 @@
+use std::fs;
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chronicle::server::log::store::{FRAME_WIDTH, HEADER_WIDTH, MAGIC, VERSION};
+use chronicle::server::log::{Compression, EncryptionConfig, Error, Log};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{config, keyed};
+
+// Rewrites every segment the way older releases laid them out: without the
+// key id in front of each frame's batch before version 4, without the codec
+// byte before version 3 and without the store header in version 1. Indexes
+// are dropped since positions move.
+fn downgrade(dir: &Path, version: u32) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("store") => {
+                let data = fs::read(&path).unwrap();
+                let mut old: Vec<u8> = Vec::new();
+                if version > 1 {
+                    old.extend_from_slice(&MAGIC);
+                    old.extend_from_slice(&version.to_be_bytes());
+                }
+                let mut pos = HEADER_WIDTH as usize;
+                while pos < data.len() {
+                    let len = u64::from_be_bytes(data[pos..pos + 8].try_into().unwrap()) as usize;
+                    let frame = &data[pos + FRAME_WIDTH as usize..][..len];
+                    assert_eq!(frame[..5], [0; 5], "batch is encrypted or compressed");
+                    let batch = if version == 3 {
+                        &frame[4..]
+                    } else {
+                        &frame[5..]
+                    };
+                    old.extend_from_slice(&(batch.len() as u64).to_be_bytes());
+                    old.extend_from_slice(&crc32fast::hash(batch).to_be_bytes());
+                    old.extend_from_slice(batch);
+                    pos += FRAME_WIDTH as usize + len;
+                }
+                fs::write(&path, old).unwrap();
+            }
+            Some("index") => fs::remove_file(&path).unwrap(),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn stores_start_with_magic_and_version() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    log.append(keyed("a", Some("a1"))).unwrap();
+    log.close().unwrap();
+
+    let data = fs::read(dir.path().join("0.store")).unwrap();
+    assert_eq!(data[..4], MAGIC);
+    assert_eq!(data[4..8], VERSION.to_be_bytes());
+}
+
+fn reads_and_appends_to_old_stores(version: u32) {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    for i in 0..4 {
+        log.append(keyed("a", Some(&i.to_string()))).unwrap();
+    }
+    log.close().unwrap();
+    downgrade(dir.path(), version);
+
+    let mut config = config();
+    config.compression = Compression::Lz4;
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    for i in 0..4 {
+        assert_eq!(
+            log.read(i).unwrap().value.unwrap(),
+            i.to_string().as_bytes()
+        );
+    }
+    assert_eq!(log.append(keyed("a", Some("4"))).unwrap(), 4);
+    log.close().unwrap();
+
+    // Appends go to a new segment in the current layout, leaving the old
+    // active segment as it was until compaction rewrites it.
+    let old = fs::read(dir.path().join("3.store")).unwrap();
+    assert_eq!(old[..4] == MAGIC, version > 1);
+    let mut log = Log::open(dir.path(), config).unwrap();
+    for i in 5..8 {
+        log.append(keyed("a", Some(&i.to_string()))).unwrap();
+    }
+    log.compact().unwrap();
+    for base in ["0", "3", "4", "7"] {
+        let data = fs::read(dir.path().join(format!("{base}.store"))).unwrap();
+        assert_eq!(data[..8], [&MAGIC[..], &VERSION.to_be_bytes()].concat());
+    }
+    assert!(matches!(log.read(5), Err(Error::Compacted { .. })));
+    assert_eq!(log.read(7).unwrap().value.unwrap(), b"7"[..]);
+}
+
+#[test]
+fn reads_and_appends_to_headerless_stores() {
+    reads_and_appends_to_old_stores(1);
+}
+
+#[test]
+fn reads_and_appends_to_uncompressed_stores() {
+    reads_and_appends_to_old_stores(2);
+}
+
+#[test]
+fn reads_and_appends_to_unencrypted_stores() {
+    reads_and_appends_to_old_stores(3);
+}
+
+#[test]
+fn old_logs_encrypt_appends_once_a_key_is_configured() {
+    let keys = TempDir::new().unwrap();
+    let key_file = keys.path().join("keys");
+    fs::write(&key_file, format!("1 {}\n", STANDARD.encode([7u8; 32]))).unwrap();
+    let mut encrypted = config();
+    encrypted.encryption = Some(EncryptionConfig {
+        key_file,
+        active_key_id: 1,
+    });
+
+    for version in [1, 2, 3] {
+        let dir = TempDir::new().unwrap();
+        let mut log = Log::open(dir.path(), config()).unwrap();
+        log.append(keyed("a", Some("plain"))).unwrap();
+        log.close().unwrap();
+        downgrade(dir.path(), version);
+
+        let mut log = Log::open(dir.path(), encrypted.clone()).unwrap();
+        assert_eq!(log.append(keyed("a", Some("secret"))).unwrap(), 1);
+        log.close().unwrap();
+
+        let data = fs::read(dir.path().join("1.store")).unwrap();
+        assert_eq!(data[..8], [&MAGIC[..], &VERSION.to_be_bytes()].concat());
+        assert!(!data.windows(6).any(|window| window == b"secret"));
+        let log = Log::open(dir.path(), encrypted.clone()).unwrap();
+        assert_eq!(log.read(0).unwrap().value.unwrap(), b"plain"[..]);
+        assert_eq!(log.read(1).unwrap().value.unwrap(), b"secret"[..]);
+    }
+}
+
+#[test]
+fn refuses_unknown_versions() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    log.append(keyed("a", Some("a1"))).unwrap();
+    log.close().unwrap();
+
+    let path = dir.path().join("0.store");
+    let mut data = fs::read(&path).unwrap();
+    data[4..8].copy_from_slice(&(VERSION + 1).to_be_bytes());
+    fs::write(&path, data).unwrap();
+
+    let version = VERSION + 1;
+    assert!(matches!(
+        Log::open(dir.path(), config()),
+        Err(Error::UnsupportedVersion { version: v }) if v == version
+    ));
+}
+
+#[test]
+fn replaces_a_torn_header() {
+    let dir = TempDir::new().unwrap();
+    Log::open(dir.path(), config()).unwrap().close().unwrap();
+    let path = dir.path().join("0.store");
+    fs::write(&path, &MAGIC[..2]).unwrap();
+
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    assert_eq!(log.append(keyed("a", Some("a1"))).unwrap(), 0);
+    assert_eq!(log.read(0).unwrap().value.unwrap(), b"a1"[..]);
+}
@@ -0,0 +1,364 @@
+use bytes::Bytes;
+use chronicle::server::http::routes;
+use chronicle::server::log::index::ENT_WIDTH;
+use chronicle::server::log::{Config, Header, Log, Record, SharedLog};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use warp::http::StatusCode;
+
+fn shared_log(dir: &TempDir) -> SharedLog {
+    SharedLog::new(Log::open(dir.path(), Config::default()).unwrap())
+}
+
+#[tokio::test]
+async fn produce_then_consume() {
+    let dir = TempDir::new().unwrap();
+    let api = routes(shared_log(&dir));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/")
+        .json(&json!({ "record": { "value": "aGVsbG8=", "event_time": 1234 } }))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body, json!({ "offset": 0 }));
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/?offset=0")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["record"]["value"], "aGVsbG8=");
+    assert_eq!(body["record"]["offset"], 0);
+    assert_eq!(body["record"]["event_time"], 1234);
+    assert!(body["record"]["timestamp"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn consume_out_of_range_is_not_found() {
+    let dir = TempDir::new().unwrap();
+    let api = routes(shared_log(&dir));
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/?offset=7")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn malformed_produce_is_bad_request() {
+    let dir = TempDir::new().unwrap();
+    let api = routes(shared_log(&dir));
+
+    for body in [
+        &b"{ \"record\": "[..],
+        br#"{ "value": "aGVsbG8=" }"#,
+        br#"{ "record": { "value": "not base64!" } }"#,
+    ] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/")
+            .header("content-type", "application/json")
+            .body(body)
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // Nothing was appended.
+    let res = warp::test::request()
+        .method("GET")
+        .path("/?offset=0")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn closed_log_is_unavailable() {
+    let dir = TempDir::new().unwrap();
+    let log = shared_log(&dir);
+    let api = routes(log.clone());
+    log.close().unwrap();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/?offset=0")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn produce_batch() {
+    let dir = TempDir::new().unwrap();
+    let api = routes(shared_log(&dir));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/batch")
+        .json(&json!({ "records": [{ "value": "YQ==" }, { "value": "Yg==" }] }))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body, json!({ "first_offset": 0, "last_offset": 1 }));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/batch")
+        .json(&json!({ "records": [] }))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn consume_range() {
+    let dir = TempDir::new().unwrap();
+    let log = shared_log(&dir);
+    let api = routes(log.clone());
+    for value in ["a", "b", "c"] {
+        log.append(Record {
+            value: Some(Bytes::copy_from_slice(value.as_bytes())),
+            ..Default::default()
+        })
+        .unwrap();
+    }
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/records?offset=1&max_records=1")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["next_offset"], 2);
+    let records = body["records"].as_array().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["value"], "Yg==");
+    assert_eq!(records[0]["offset"], 1);
+}
+
+#[tokio::test]
+async fn consume_raw_value() {
+    let dir = TempDir::new().unwrap();
+    let log = shared_log(&dir);
+    let api = routes(log.clone());
+    log.append(Record {
+        value: Some(Bytes::from_static(b"\x00raw\xff")),
+        key: Some(Bytes::from_static(b"user-1")),
+        headers: vec![
+            Header {
+                key: "source".to_string(),
+                value: Bytes::from_static(b"web"),
+            },
+            Header {
+                key: "trace".to_string(),
+                value: Bytes::from_static(b"a"),
+            },
+            Header {
+                key: "trace".to_string(),
+                value: Bytes::from_static(b"b"),
+            },
+            Header {
+                key: "not a name".to_string(),
+                value: Bytes::from_static(b"dropped"),
+            },
+        ],
+        ..Default::default()
+    })
+    .unwrap();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/value?offset=0")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "application/octet-stream");
+    assert_eq!(res.headers()["chronicle-offset"], "0");
+    assert_eq!(res.headers()["chronicle-key"], "dXNlci0x");
+    assert_eq!(res.headers()["chronicle-header-source"], "d2Vi");
+    let traces: Vec<_> = res
+        .headers()
+        .get_all("chronicle-header-trace")
+        .iter()
+        .collect();
+    assert_eq!(traces, ["YQ==", "Yg=="]);
+    assert_eq!(
+        res.headers()
+            .keys()
+            .filter(|name| name.as_str().starts_with("chronicle-header-"))
+            .count(),
+        2
+    );
+    assert_eq!(res.body().as_ref(), b"\x00raw\xff");
+}
+
+#[tokio::test]
+async fn keys_and_headers_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let api = routes(shared_log(&dir));
+    let record = json!({
+        "value": "aGVsbG8=",
+        "key": "dXNlci0x",
+        "headers": [{ "key": "source", "value": "d2Vi" }],
+    });
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/")
+        .json(&json!({ "record": record }))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/records?offset=0")
+        .reply(&api)
+        .await;
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    let read = &body["records"][0];
+    assert_eq!(read["key"], record["key"]);
+    assert_eq!(read["headers"], record["headers"]);
+}
+
+#[tokio::test]
+async fn admin_stats() {
+    let dir = TempDir::new().unwrap();
+    let log = shared_log(&dir);
+    let api = routes(log.clone());
+    log.append(Record {
+        value: Some(Bytes::from_static(b"event")),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/admin/stats")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    let raw_bytes = body["raw_bytes"].as_u64().unwrap();
+    assert!(raw_bytes > 0);
+    // Only the codec byte is added.
+    assert_eq!(body["stored_bytes"], raw_bytes + 1);
+}
+
+#[tokio::test]
+async fn admin_truncate() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.segment.max_index_bytes = 2 * ENT_WIDTH;
+    let log = SharedLog::new(Log::open(dir.path(), config).unwrap());
+    let api = routes(log.clone());
+    for _ in 0..5 {
+        log.append(Record {
+            value: Some(Bytes::from_static(b"event")),
+            ..Default::default()
+        })
+        .unwrap();
+    }
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/admin/truncate")
+        .json(&json!({ "lowest": 3 }))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body, json!({ "lowest_offset": 2, "highest_offset": 4 }));
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/?offset=1")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn admin_snapshot() {
+    let dir = TempDir::new().unwrap();
+    let log = shared_log(&dir);
+    let api = routes(log.clone());
+    for _ in 0..3 {
+        log.append(Record {
+            value: Some(Bytes::from_static(b"event")),
+            ..Default::default()
+        })
+        .unwrap();
+    }
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/admin/snapshot?upto=1")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "application/x-tar");
+    assert_eq!(res.headers()["chronicle-lowest-offset"], "0");
+    assert_eq!(res.headers()["chronicle-next-offset"], "2");
+    assert_eq!(res.headers()["chronicle-segments"], "1");
+
+    let restored = TempDir::new().unwrap();
+    let restored = Log::restore(&res.body()[..], restored.path(), Config::default()).unwrap();
+    assert_eq!(restored.highest_offset(), Some(1));
+    assert_eq!(restored.read(1).unwrap().value.unwrap(), b"event"[..]);
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/admin/snapshot?upto=abc")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn seek_by_time() {
+    let dir = TempDir::new().unwrap();
+    let log = shared_log(&dir);
+    let api = routes(log.clone());
+    log.append(Record {
+        value: Some(Bytes::from_static(b"event")),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/offset?timestamp=2000-01-01T00:00:00Z")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body, json!({ "offset": 0 }));
+
+    // Nothing has been appended in the future yet, so seeking there lands at
+    // the end of the log.
+    let res = warp::test::request()
+        .method("GET")
+        .path("/offset?timestamp=32503680000000")
+        .reply(&api)
+        .await;
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body, json!({ "offset": 1 }));
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/offset?timestamp=yesterday")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
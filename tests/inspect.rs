@@ -0,0 +1,307 @@
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use chronicle::server::log::index::ENT_WIDTH;
+use chronicle::server::log::inspect::{self, Outcome, Repair};
+use chronicle::server::log::store::{FRAME_WIDTH, HEADER_WIDTH};
+use chronicle::server::log::{EncryptionConfig, Log};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{config, record};
+
+fn fill(dir: &Path) -> Log {
+    let mut log = Log::open(dir, config()).unwrap();
+    for i in 0..7 {
+        log.append(record(i)).unwrap();
+    }
+    log
+}
+
+// Every file in the directory along with its contents.
+fn contents(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<(String, Vec<u8>)> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read(path).unwrap())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn inspects_and_dumps_without_touching_the_log() {
+    let dir = TempDir::new().unwrap();
+    // The active segment's index is still padded while the log is open.
+    let log = fill(dir.path());
+    let before = contents(dir.path());
+
+    let reports = inspect::inspect(dir.path(), &config()).unwrap();
+    let ranges: Vec<(u64, Option<u64>, Option<u64>, u64)> = reports
+        .iter()
+        .map(|report| {
+            (
+                report.base_offset,
+                report.first_offset,
+                report.last_offset,
+                report.records,
+            )
+        })
+        .collect();
+    assert_eq!(
+        ranges,
+        [
+            (0, Some(0), Some(2), 3),
+            (3, Some(3), Some(5), 3),
+            (6, Some(6), Some(6), 1)
+        ]
+    );
+    for report in &reports {
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+    }
+
+    let mut offsets: Vec<u64> = Vec::new();
+    inspect::dump(dir.path(), &config(), 2, 4, |record| {
+        assert_eq!(record.value, record_value(record.offset));
+        offsets.push(record.offset);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(offsets, [2, 3, 4]);
+    assert_eq!(contents(dir.path()), before);
+    log.close().unwrap();
+}
+
+fn record_value(i: u64) -> Option<Bytes> {
+    record(i).value
+}
+
+#[test]
+fn indexes_left_open_by_a_crash_pass_inspection() {
+    let dir = TempDir::new().unwrap();
+    // Killed before the first append, the index is nothing but padding.
+    mem::forget(Log::open(dir.path(), config()).unwrap());
+    let reports = inspect::inspect(dir.path(), &config()).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert!(reports[0].problems.is_empty(), "{:?}", reports[0].problems);
+
+    let dir = TempDir::new().unwrap();
+    mem::forget(fill(dir.path()));
+    let reports = inspect::inspect(dir.path(), &config()).unwrap();
+    assert!(reports.iter().all(|report| report.problems.is_empty()));
+}
+
+#[test]
+fn repair_rebuilds_broken_indexes() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+
+    let mut index = fs::read(dir.path().join("3.index")).unwrap();
+    index[ENT_WIDTH as usize + 4..2 * ENT_WIDTH as usize].fill(0xff);
+    fs::write(dir.path().join("3.index"), index).unwrap();
+    fs::remove_file(dir.path().join("6.timeindex")).unwrap();
+
+    let reports = inspect::inspect(dir.path(), &config()).unwrap();
+    let problems: Vec<usize> = reports.iter().map(|report| report.problems.len()).collect();
+    assert_eq!(problems, [0, 1, 1]);
+    assert!(reports[1].problems[0].starts_with("index entry 1"));
+    assert_eq!(reports[2].problems[0], "time index file is missing");
+
+    let repairs = inspect::repair(dir.path(), &config(), false).unwrap();
+    let repaired: Vec<u64> = repairs.iter().map(|repair| repair.base_offset).collect();
+    assert_eq!(repaired, [3, 6]);
+    assert!(repairs
+        .iter()
+        .all(|repair| repair.outcome == Outcome::Applied
+            && repair.store_bytes.0 == repair.store_bytes.1));
+    let reports = inspect::inspect(dir.path(), &config()).unwrap();
+    assert!(reports.iter().all(|report| report.problems.is_empty()));
+
+    let log = Log::open(dir.path(), config()).unwrap();
+    assert_eq!(log.read(4).unwrap().value, record_value(4));
+}
+
+// Flips a byte in the payload of the given frame of segment 0, whose three
+// frames are all the same size, and returns the store's size and where the
+// frame starts.
+fn corrupt(dir: &Path, frame: u64) -> (u64, u64) {
+    let path = dir.join("0.store");
+    let mut store = fs::read(&path).unwrap();
+    let size = store.len() as u64;
+    let start = HEADER_WIDTH + frame * (size - HEADER_WIDTH) / 3;
+    store[(start + FRAME_WIDTH) as usize] ^= 0xff;
+    fs::write(&path, store).unwrap();
+    (size, start)
+}
+
+#[test]
+fn repair_cuts_stores_at_their_first_corrupt_frame() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+    let (size, torn_at) = corrupt(dir.path(), 2);
+
+    let reports = inspect::inspect(dir.path(), &config()).unwrap();
+    assert_eq!(reports[0].records, 2);
+    assert_eq!(
+        reports[0].problems,
+        [format!(
+            "store is unreadable from position {torn_at} on, where a frame fails its checksum"
+        )]
+    );
+
+    let repairs = inspect::repair(dir.path(), &config(), false).unwrap();
+    assert_eq!(
+        repairs,
+        [Repair {
+            base_offset: 0,
+            store_bytes: (size, torn_at),
+            dropped_offsets: None,
+            recreated: false,
+            outcome: Outcome::Applied,
+        }]
+    );
+    let log = Log::open(dir.path(), config()).unwrap();
+    assert_eq!(log.read(1).unwrap().value, record_value(1));
+    assert!(log.read(2).is_err());
+    assert_eq!(log.read(3).unwrap().value, record_value(3));
+}
+
+#[test]
+fn repair_only_drops_readable_records_when_forced() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+    let (size, torn_at) = corrupt(dir.path(), 0);
+
+    let reports = inspect::inspect(dir.path(), &config()).unwrap();
+    assert_eq!(reports[0].records, 0);
+    assert_eq!(reports[0].stranded_records, 2);
+    assert_eq!(reports[0].stranded_last_offset, Some(2));
+    assert_eq!(
+        reports[0].problems[1],
+        "2 readable records up to offset 2 follow the unreadable frame"
+    );
+
+    let before = contents(dir.path());
+    let refused = inspect::repair(dir.path(), &config(), false).unwrap();
+    let expected = Repair {
+        base_offset: 0,
+        store_bytes: (size, torn_at),
+        dropped_offsets: Some((0, 2)),
+        recreated: false,
+        outcome: Outcome::NeedsForce,
+    };
+    assert_eq!(refused.len(), 1);
+    assert_eq!(refused[0], expected);
+    assert_eq!(contents(dir.path()), before);
+
+    let forced = inspect::repair(dir.path(), &config(), true).unwrap();
+    assert_eq!(
+        forced,
+        [Repair {
+            outcome: Outcome::Applied,
+            ..expected
+        }]
+    );
+    let log = Log::open(dir.path(), config()).unwrap();
+    assert!(log.read(2).is_err());
+    assert_eq!(log.read(3).unwrap().value, record_value(3));
+}
+
+#[test]
+fn repair_recreates_missing_stores_only_when_forced() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+    fs::remove_file(dir.path().join("3.store")).unwrap();
+
+    let before = contents(dir.path());
+    let expected = Repair {
+        base_offset: 3,
+        store_bytes: (0, HEADER_WIDTH),
+        dropped_offsets: None,
+        recreated: true,
+        outcome: Outcome::NeedsForce,
+    };
+    let refused = inspect::repair(dir.path(), &config(), false).unwrap();
+    assert_eq!(refused.len(), 1);
+    assert_eq!(refused[0], expected);
+    assert_eq!(contents(dir.path()), before);
+
+    let forced = inspect::repair(dir.path(), &config(), true).unwrap();
+    assert_eq!(
+        forced,
+        [Repair {
+            outcome: Outcome::Applied,
+            ..expected
+        }]
+    );
+    let reports = inspect::inspect(dir.path(), &config()).unwrap();
+    assert!(reports.iter().all(|report| report.problems.is_empty()));
+}
+
+#[test]
+fn repair_leaves_stores_it_cannot_read_alone() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+    let path = dir.path().join("3.store");
+    let mut store = fs::read(&path).unwrap();
+    store[HEADER_WIDTH as usize - 4..HEADER_WIDTH as usize].copy_from_slice(&9u32.to_be_bytes());
+    fs::write(&path, store).unwrap();
+
+    let before = contents(dir.path());
+    let repairs = inspect::repair(dir.path(), &config(), true).unwrap();
+    assert_eq!(repairs.len(), 1);
+    assert_eq!(repairs[0].base_offset, 3);
+    assert!(
+        matches!(&repairs[0].outcome, Outcome::Unrepairable(reason) if reason.contains('9')),
+        "{repairs:?}"
+    );
+    assert_eq!(contents(dir.path()), before);
+}
+
+#[test]
+fn repair_leaves_batches_without_their_key_alone() {
+    let (dir, keys) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let encrypted = |key: u8| {
+        let key_file = keys.path().join(format!("keys-{key}"));
+        fs::write(&key_file, format!("{key} {}\n", STANDARD.encode([key; 32]))).unwrap();
+        let mut config = config();
+        config.encryption = Some(EncryptionConfig {
+            key_file,
+            active_key_id: key.into(),
+        });
+        config
+    };
+    let mut log = Log::open(dir.path(), encrypted(1)).unwrap();
+    for i in 0..4 {
+        log.append(record(i)).unwrap();
+    }
+    log.close().unwrap();
+
+    let before = contents(dir.path());
+    let repairs = inspect::repair(dir.path(), &encrypted(2), true).unwrap();
+    let outcomes: Vec<(u64, bool)> = repairs
+        .iter()
+        .map(|repair| {
+            let unrepairable = matches!(repair.outcome, Outcome::Unrepairable(_));
+            (repair.base_offset, unrepairable)
+        })
+        .collect();
+    assert_eq!(outcomes, [(0, true), (3, true)]);
+    assert_eq!(contents(dir.path()), before);
+    assert_eq!(
+        Log::open(dir.path(), encrypted(1))
+            .unwrap()
+            .read(3)
+            .unwrap()
+            .value,
+        record_value(3)
+    );
+}
@@ -0,0 +1,460 @@
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use bytes::Bytes;
+use chronicle::server::log::index::ENT_WIDTH;
+use chronicle::server::log::{Config, Error, Header, Log, Record, SharedLog, SyncPolicy};
+use tempfile::TempDir;
+
+mod common;
+
+use common::wait_until;
+
+fn record(value: &str) -> Record {
+    Record {
+        value: Some(Bytes::copy_from_slice(value.as_bytes())),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn append_and_read() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), Config::default()).unwrap();
+
+    assert_eq!(log.append(record("hello")).unwrap(), 0);
+    assert_eq!(log.append(record("world")).unwrap(), 1);
+
+    let read = log.read(1).unwrap();
+    assert_eq!(read.offset, 1);
+    assert_eq!(read.value.unwrap(), b"world"[..]);
+    assert!(log.read(2).is_err());
+}
+
+#[test]
+fn records_survive_reopen() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), Config::default()).unwrap();
+    for i in 0..3 {
+        log.append(record(&format!("record-{i}"))).unwrap();
+    }
+    log.close().unwrap();
+
+    let mut log = Log::open(dir.path(), Config::default()).unwrap();
+    for i in 0..3 {
+        assert_eq!(
+            log.read(i).unwrap().value.unwrap(),
+            format!("record-{i}").as_bytes()
+        );
+    }
+    assert_eq!(log.append(record("record-3")).unwrap(), 3);
+}
+
+#[test]
+fn a_log_is_only_opened_once_at_a_time() {
+    let dir = TempDir::new().unwrap();
+    let log = Log::open(dir.path(), Config::default()).unwrap();
+    assert!(matches!(
+        Log::open(dir.path(), Config::default()),
+        Err(Error::Locked { .. })
+    ));
+
+    log.close().unwrap();
+    Log::open(dir.path(), Config::default()).unwrap();
+}
+
+#[test]
+fn index_is_truncated_on_close() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), Config::default()).unwrap();
+    for i in 0..3 {
+        log.append(record(&format!("record-{i}"))).unwrap();
+    }
+    let index_path = dir.path().join("0.index");
+    assert_eq!(
+        fs::metadata(&index_path).unwrap().len(),
+        Config::default().segment.max_index_bytes
+    );
+
+    log.close().unwrap();
+    assert_eq!(fs::metadata(&index_path).unwrap().len(), 3 * ENT_WIDTH);
+}
+
+#[test]
+fn missing_index_is_rebuilt_from_store() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), Config::default()).unwrap();
+    for i in 0..3 {
+        log.append(record(&format!("record-{i}"))).unwrap();
+    }
+    log.close().unwrap();
+    fs::remove_file(dir.path().join("0.index")).unwrap();
+
+    let mut log = Log::open(dir.path(), Config::default()).unwrap();
+    assert_eq!(log.read(2).unwrap().value.unwrap(), b"record-2"[..]);
+    assert_eq!(log.append(record("record-3")).unwrap(), 3);
+}
+
+#[test]
+fn rolls_to_new_segment_when_maxed() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.segment.max_store_bytes = 150;
+    config.segment.max_index_bytes = 3 * ENT_WIDTH;
+    let mut log = Log::open(dir.path(), config).unwrap();
+
+    for i in 0..10 {
+        assert_eq!(log.append(record(&format!("record-{i}"))).unwrap(), i);
+    }
+    for i in 0..10 {
+        let read = log.read(i).unwrap();
+        assert_eq!(read.offset, i);
+        assert_eq!(read.value.unwrap(), format!("record-{i}").as_bytes());
+    }
+    assert!(log.read(10).is_err());
+
+    let mut stores: Vec<String> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".store"))
+        .collect();
+    stores.sort();
+    assert_eq!(stores, ["0.store", "3.store", "6.store", "9.store"]);
+}
+
+#[test]
+fn starts_at_initial_offset() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.segment.initial_offset = 16;
+    let mut log = Log::open(dir.path(), config).unwrap();
+
+    assert_eq!(log.append(record("first")).unwrap(), 16);
+    assert_eq!(log.read(16).unwrap().value.unwrap(), b"first"[..]);
+    assert!(log.read(15).is_err());
+}
+
+fn open_with_policy(dir: &TempDir, sync: SyncPolicy) -> Log {
+    let config = Config {
+        sync,
+        ..Config::default()
+    };
+    Log::open(dir.path(), config).unwrap()
+}
+
+#[test]
+fn sync_policies_decide_when_appends_are_fsynced() {
+    let dir = TempDir::new().unwrap();
+    let mut log = open_with_policy(&dir, SyncPolicy::Always);
+    for i in 1..=3 {
+        log.append(record("always")).unwrap();
+        assert_eq!((log.syncs(), log.unsynced()), (i, 0));
+    }
+
+    let dir = TempDir::new().unwrap();
+    let mut log = open_with_policy(&dir, SyncPolicy::EveryRecords(3));
+    log.append(record("one")).unwrap();
+    log.append(record("two")).unwrap();
+    assert_eq!((log.syncs(), log.unsynced()), (0, 2));
+    log.append(record("three")).unwrap();
+    assert_eq!((log.syncs(), log.unsynced()), (1, 0));
+    log.append(record("four")).unwrap();
+    assert_eq!((log.syncs(), log.unsynced()), (1, 1));
+
+    let dir = TempDir::new().unwrap();
+    let mut log = open_with_policy(&dir, SyncPolicy::Interval(Duration::from_millis(200)));
+    log.append(record("early")).unwrap();
+    assert_eq!((log.syncs(), log.unsynced()), (0, 1));
+    thread::sleep(Duration::from_millis(250));
+    log.append(record("late")).unwrap();
+    assert_eq!((log.syncs(), log.unsynced()), (1, 0));
+
+    let dir = TempDir::new().unwrap();
+    let mut log = open_with_policy(&dir, SyncPolicy::Os);
+    for _ in 0..3 {
+        log.append(record("os")).unwrap();
+    }
+    assert_eq!((log.syncs(), log.unsynced()), (0, 3));
+    // Records reach the file either way, and an explicit sync fsyncs them.
+    assert!(fs::metadata(dir.path().join("0.store")).unwrap().len() > 0);
+    log.sync().unwrap();
+    assert_eq!((log.syncs(), log.unsynced()), (1, 0));
+}
+
+#[test]
+fn interval_policy_syncs_in_the_background_once_appends_stop() {
+    let dir = TempDir::new().unwrap();
+    let log = SharedLog::new(open_with_policy(
+        &dir,
+        SyncPolicy::Interval(Duration::from_millis(50)),
+    ));
+    log.append(record("idle")).unwrap();
+    let syncs = log.syncs().unwrap();
+    assert_eq!(log.unsynced().unwrap(), 1);
+
+    wait_until("the interval sync runs", || log.unsynced().unwrap() == 0);
+    assert_eq!(log.syncs().unwrap(), syncs + 1);
+}
+
+#[test]
+fn append_batch_assigns_contiguous_offsets() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.segment.max_index_bytes = 4 * ENT_WIDTH;
+    let mut log = Log::open(dir.path(), config).unwrap();
+
+    assert_eq!(log.append(record("single")).unwrap(), 0);
+    let batch: Vec<Record> = (1..4).map(|i| record(&format!("batch-{i}"))).collect();
+    assert_eq!(log.append_batch(batch).unwrap(), (1, 3));
+
+    // The active segment has room for one more record, so a batch of two
+    // starts a new segment rather than being split across both.
+    let batch: Vec<Record> = (4..6).map(|i| record(&format!("batch-{i}"))).collect();
+    assert_eq!(log.append_batch(batch).unwrap(), (4, 5));
+    assert!(dir.path().join("4.store").exists());
+
+    for i in 1..6 {
+        assert_eq!(
+            log.read(i).unwrap().value.unwrap(),
+            format!("batch-{i}").as_bytes()
+        );
+    }
+}
+
+#[test]
+fn append_batch_rejects_empty_and_oversized_batches() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.segment.max_index_bytes = 4 * ENT_WIDTH;
+    let mut log = Log::open(dir.path(), config).unwrap();
+
+    assert!(matches!(
+        log.append_batch(Vec::new()),
+        Err(Error::EmptyBatch)
+    ));
+    let batch: Vec<Record> = (0..5).map(|i| record(&i.to_string())).collect();
+    assert!(matches!(
+        log.append_batch(batch),
+        Err(Error::BatchTooLarge { count: 5, max: 4 })
+    ));
+    assert_eq!(log.append(record("next")).unwrap(), 0);
+}
+
+#[test]
+fn failed_commit_takes_the_records_back() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.segment.max_index_bytes = 3 * ENT_WIDTH;
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    assert_eq!(
+        log.append_batch(vec![record("a"), record("b")]).unwrap(),
+        (0, 1)
+    );
+    let store_len = fs::metadata(dir.path().join("0.store")).unwrap().len();
+
+    log.inject_commit_failures(1);
+    assert!(log.append(record("lost")).is_err());
+    assert!(matches!(log.read(2), Err(Error::OffsetOutOfRange(2))));
+    assert_eq!(log.highest_offset(), Some(1));
+    assert_eq!(
+        fs::metadata(dir.path().join("0.store")).unwrap().len(),
+        store_len
+    );
+
+    // A batch that rolled to a new segment takes the roll back with it.
+    log.inject_commit_failures(1);
+    assert!(log
+        .append_batch(vec![record("lost"), record("lost")])
+        .is_err());
+    assert!(!dir.path().join("2.store").exists());
+    assert_eq!(log.highest_offset(), Some(1));
+
+    // The offsets are handed out again, and the segment that was sealed by
+    // the roll takes appends again.
+    assert_eq!(log.append(record("c")).unwrap(), 2);
+    assert_eq!(log.append(record("d")).unwrap(), 3);
+    log.close().unwrap();
+
+    let log = Log::open(dir.path(), config).unwrap();
+    assert_eq!(log.highest_offset(), Some(3));
+    for (offset, value) in ["a", "b", "c", "d"].into_iter().enumerate() {
+        assert_eq!(
+            log.read(offset as u64).unwrap().value.unwrap(),
+            value.as_bytes()
+        );
+    }
+}
+
+#[test]
+fn concurrent_appends_share_fsyncs() {
+    let dir = TempDir::new().unwrap();
+    let log = SharedLog::new(open_with_policy(&dir, SyncPolicy::Always));
+    let writers: Vec<_> = (0..8)
+        .map(|_| {
+            let log = log.clone();
+            thread::spawn(move || {
+                (0..50)
+                    .map(|_| log.append(record("event")).unwrap())
+                    .collect::<Vec<u64>>()
+            })
+        })
+        .collect();
+    let mut offsets: Vec<u64> = writers
+        .into_iter()
+        .flat_map(|writer| writer.join().unwrap())
+        .collect();
+    offsets.sort();
+    assert_eq!(offsets, (0..400).collect::<Vec<u64>>());
+
+    // Every append was durable when it returned, yet appends that queued up
+    // behind an fsync were committed together by the next one.
+    assert_eq!(log.unsynced().unwrap(), 0);
+    let syncs = log.syncs().unwrap();
+    assert!(syncs < 400, "{syncs} fsyncs for 400 appends");
+}
+
+#[test]
+fn failed_group_commit_takes_the_whole_group_back() {
+    let dir = TempDir::new().unwrap();
+    let mut log = open_with_policy(&dir, SyncPolicy::Always);
+    log.append(record("kept")).unwrap();
+    log.inject_commit_failures(1);
+    let log = SharedLog::new(log);
+
+    assert!(log
+        .append_batch(vec![record("lost"), record("lost")])
+        .is_err());
+    assert!(matches!(log.read(1), Err(Error::OffsetOutOfRange(1))));
+    assert_eq!(log.highest_offset().unwrap(), Some(0));
+    assert_eq!(log.append(record("next")).unwrap(), 1);
+    assert_eq!(log.read(1).unwrap().value.unwrap(), b"next"[..]);
+}
+
+#[test]
+fn read_range_pages_across_segments() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.segment.max_index_bytes = 3 * ENT_WIDTH;
+    let mut log = Log::open(dir.path(), config).unwrap();
+    for i in 0..10 {
+        log.append(record(&format!("record-{i}"))).unwrap();
+    }
+
+    let (records, next) = log.read_range(2, 5, u64::MAX).unwrap();
+    let offsets: Vec<u64> = records.iter().map(|record| record.offset).collect();
+    assert_eq!(offsets, [2, 3, 4, 5, 6]);
+    assert_eq!(records[4].value.as_ref().unwrap(), &b"record-6"[..]);
+    assert_eq!(next, 7);
+
+    // Each value is 8 bytes, so 20 bytes fit two records.
+    let (records, next) = log.read_range(next, 100, 20).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(next, 9);
+
+    // A record larger than max_bytes is still returned on its own.
+    let (records, next) = log.read_range(next, 100, 1).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(next, 10);
+
+    let (records, next) = log.read_range(next, 100, u64::MAX).unwrap();
+    assert!(records.is_empty());
+    assert_eq!(next, 10);
+    assert!(matches!(
+        log.read_range(11, 100, u64::MAX),
+        Err(Error::OffsetOutOfRange(11))
+    ));
+}
+
+#[test]
+fn sealed_segments_share_one_buffer_across_reads() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.segment.max_index_bytes = 2 * ENT_WIDTH;
+    let mut log = Log::open(dir.path(), config).unwrap();
+    for i in 0..3 {
+        log.append(record(&format!("record-{i}"))).unwrap();
+    }
+
+    // Offset 0 lives in a sealed segment, so both reads slice the same map.
+    let first = log.read(0).unwrap();
+    let second = log.read(0).unwrap();
+    let (first, second) = (first.value.unwrap(), second.value.unwrap());
+    assert_eq!(first, b"record-0"[..]);
+    assert_eq!(first.as_ptr(), second.as_ptr());
+}
+
+#[test]
+fn records_are_stamped_with_non_decreasing_timestamps() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), Config::default()).unwrap();
+
+    let mut event = record("event");
+    event.event_time = Some(1234);
+    log.append(event).unwrap();
+    log.append(record("plain")).unwrap();
+
+    let first = log.read(0).unwrap();
+    let second = log.read(1).unwrap();
+    assert_eq!(first.event_time, Some(1234));
+    assert_eq!(second.event_time, None);
+    assert!(first.timestamp > 0);
+    assert!(second.timestamp >= first.timestamp);
+}
+
+#[test]
+fn offset_for_time_seeks_across_segments_and_reopens() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.segment.max_index_bytes = 2 * ENT_WIDTH;
+
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    let mut stamps: Vec<u64> = Vec::new();
+    for i in 0..5 {
+        log.append(record(&i.to_string())).unwrap();
+        stamps.push(log.read(i).unwrap().timestamp);
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    assert_eq!(log.offset_for_time(0).unwrap(), 0);
+    assert_eq!(log.offset_for_time(stamps[3]).unwrap(), 3);
+    assert_eq!(log.offset_for_time(stamps[2] + 1).unwrap(), 3);
+    assert_eq!(log.offset_for_time(stamps[4] + 1).unwrap(), 5);
+    log.close().unwrap();
+
+    // The time index of the active segment is rebuilt from its store.
+    fs::remove_file(dir.path().join("4.timeindex")).unwrap();
+    let log = Log::open(dir.path(), config).unwrap();
+    assert_eq!(log.offset_for_time(stamps[3]).unwrap(), 3);
+    assert_eq!(log.offset_for_time(stamps[4]).unwrap(), 4);
+}
+
+#[test]
+fn keys_and_headers_survive_reopen() {
+    let dir = TempDir::new().unwrap();
+    let headers = vec![
+        Header {
+            key: "source".to_string(),
+            value: Bytes::from_static(b"web"),
+        },
+        Header {
+            key: "trace".to_string(),
+            value: Bytes::new(),
+        },
+    ];
+
+    let mut log = Log::open(dir.path(), Config::default()).unwrap();
+    let mut keyed = record("keyed");
+    keyed.key = Some(Bytes::from_static(b"user-1"));
+    keyed.headers = headers.clone();
+    log.append_batch(vec![keyed, record("plain")]).unwrap();
+    log.close().unwrap();
+
+    let log = Log::open(dir.path(), Config::default()).unwrap();
+    let keyed = log.read(0).unwrap();
+    assert_eq!(keyed.key.unwrap(), b"user-1"[..]);
+    assert_eq!(keyed.headers, headers);
+    assert_eq!(keyed.value.unwrap(), b"keyed"[..]);
+    let plain = log.read(1).unwrap();
+    assert_eq!(plain.key, None);
+    assert!(plain.headers.is_empty());
+}
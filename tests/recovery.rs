@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use chronicle::server::log::index::ENT_WIDTH;
+use chronicle::server::log::store::{FRAME_WIDTH, HEADER_WIDTH};
+use chronicle::server::log::{Config, Error, Log, Record};
+use tempfile::TempDir;
+
+const RECORDS: u64 = 25;
+
+fn config() -> Config {
+    let mut config = Config::default();
+    config.segment.max_store_bytes = 128;
+    config.segment.max_index_bytes = 4 * ENT_WIDTH;
+    config
+}
+
+fn record(i: u64) -> Record {
+    Record {
+        value: Some(format!("record-{i}").into()),
+        ..Default::default()
+    }
+}
+
+fn fill(dir: &Path) -> Log {
+    let mut log = Log::open(dir, config()).unwrap();
+    for i in 0..RECORDS {
+        assert_eq!(log.append(record(i)).unwrap(), i);
+    }
+    log
+}
+
+// Every acknowledged record is readable exactly once at its offset and the
+// next append continues right after the last one.
+fn assert_recovered(dir: &Path) {
+    let mut log = Log::open(dir, config()).unwrap();
+    let mut values: HashSet<Option<Bytes>> = HashSet::new();
+    for i in 0..RECORDS {
+        let read = log.read(i).unwrap();
+        assert_eq!(read.offset, i);
+        assert_eq!(read.value, record(i).value);
+        assert!(values.insert(read.value));
+    }
+    assert!(log.read(RECORDS).is_err());
+    assert_eq!(log.append(record(RECORDS)).unwrap(), RECORDS);
+}
+
+fn index_paths(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "index"))
+        .collect()
+}
+
+#[test]
+fn recovers_after_clean_close() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+    assert_recovered(dir.path());
+}
+
+#[test]
+fn recovers_after_drop() {
+    let dir = TempDir::new().unwrap();
+    drop(fill(dir.path()));
+    assert_recovered(dir.path());
+}
+
+#[test]
+fn recovers_from_untruncated_indexes() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+
+    // A crash leaves every index at its full, zero-padded size.
+    for path in index_paths(dir.path()) {
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(config().segment.max_index_bytes).unwrap();
+    }
+    assert_recovered(dir.path());
+}
+
+#[test]
+fn recovers_from_indexes_behind_their_stores() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+
+    for path in index_paths(dir.path()) {
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(ENT_WIDTH).unwrap();
+    }
+    assert_recovered(dir.path());
+}
+
+#[test]
+fn resumes_after_repeated_restarts() {
+    let dir = TempDir::new().unwrap();
+    for i in 0..RECORDS {
+        let mut log = Log::open(dir.path(), config()).unwrap();
+        assert_eq!(log.append(record(i)).unwrap(), i);
+        log.close().unwrap();
+    }
+    assert_recovered(dir.path());
+}
+
+#[test]
+fn rejects_overlapping_segments() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+    fs::copy(dir.path().join("0.store"), dir.path().join("1.store")).unwrap();
+
+    assert!(Log::open(dir.path(), config()).is_err());
+}
+
+fn last_store(dir: &Path) -> PathBuf {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "store"))
+        .max_by_key(|path| {
+            let stem = path.file_stem().unwrap().to_str().unwrap();
+            stem.parse::<u64>().unwrap()
+        })
+        .unwrap()
+}
+
+#[test]
+fn truncates_torn_tail_record() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+    let store_path = last_store(dir.path());
+    let size = fs::metadata(&store_path).unwrap().len();
+
+    // A frame header promising more bytes than ever made it to disk.
+    let mut store = OpenOptions::new().append(true).open(&store_path).unwrap();
+    store.write_all(&64u64.to_be_bytes()).unwrap();
+    store.write_all(b"torn").unwrap();
+    drop(store);
+
+    Log::open(dir.path(), config()).unwrap().close().unwrap();
+    assert_eq!(fs::metadata(&store_path).unwrap().len(), size);
+    assert_recovered(dir.path());
+}
+
+#[test]
+fn truncates_tail_record_failing_checksum() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+    let store_path = last_store(dir.path());
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    log.append(record(RECORDS)).unwrap();
+    log.close().unwrap();
+
+    // Flip the last byte of the record appended above.
+    let mut data = fs::read(&store_path).unwrap();
+    *data.last_mut().unwrap() ^= 0xff;
+    fs::write(&store_path, data).unwrap();
+
+    assert_recovered(dir.path());
+}
+
+#[test]
+fn reports_corrupt_record_on_read() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+
+    let store_path = dir.path().join("0.store");
+    let mut data = fs::read(&store_path).unwrap();
+    data[(HEADER_WIDTH + FRAME_WIDTH) as usize + 8] ^= 0xff;
+    fs::write(&store_path, data).unwrap();
+
+    let log = Log::open(dir.path(), config()).unwrap();
+    assert!(matches!(
+        log.read(0),
+        Err(Error::Corrupt {
+            position: HEADER_WIDTH
+        })
+    ));
+    assert_eq!(log.read(1).unwrap().value, record(1).value);
+}
+
+#[test]
+fn drops_torn_batch_as_a_whole() {
+    let dir = TempDir::new().unwrap();
+    fill(dir.path()).close().unwrap();
+
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    let batch: Vec<Record> = (RECORDS..RECORDS + 3).map(record).collect();
+    assert_eq!(log.append_batch(batch).unwrap(), (RECORDS, RECORDS + 2));
+    log.close().unwrap();
+
+    // Cut the batch's frame short, as a crash partway through writing it would.
+    let store_path = last_store(dir.path());
+    let size = fs::metadata(&store_path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&store_path).unwrap();
+    file.set_len(size - 5).unwrap();
+
+    assert_recovered(dir.path());
+}
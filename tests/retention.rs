@@ -0,0 +1,122 @@
+use std::thread;
+use std::time::Duration;
+
+use chronicle::server::log::{Error, Log, SharedLog};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{config, keyed, record, wait_until};
+
+fn fill(log: &mut Log) {
+    for i in 0..10 {
+        log.append(record(i)).unwrap();
+    }
+}
+
+#[test]
+fn size_retention_deletes_oldest_segments() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config();
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    fill(&mut log);
+
+    // Keep roughly two full segments' worth of data.
+    let segment_size = std::fs::metadata(dir.path().join("0.store")).unwrap().len();
+    config.retention.max_bytes = Some(2 * segment_size);
+    log.close().unwrap();
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    log.enforce_retention().unwrap();
+
+    assert!(!dir.path().join("0.store").exists());
+    assert!(!dir.path().join("0.index").exists());
+    assert!(!dir.path().join("3.store").exists());
+    assert!(matches!(
+        log.read(5),
+        Err(Error::OffsetTruncated {
+            offset: 5,
+            lowest_offset: 6
+        })
+    ));
+    assert_eq!(log.read(6).unwrap().value, record(6).value);
+    assert_eq!(log.append(record(10)).unwrap(), 10);
+}
+
+#[test]
+fn age_retention_keeps_the_active_segment() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config();
+    config.retention.max_age = Some(Duration::ZERO);
+    let mut log = Log::open(dir.path(), config).unwrap();
+    fill(&mut log);
+    thread::sleep(Duration::from_millis(10));
+
+    log.enforce_retention().unwrap();
+    assert!(matches!(
+        log.read_range(0, 10, u64::MAX),
+        Err(Error::OffsetTruncated {
+            offset: 0,
+            lowest_offset: 9
+        })
+    ));
+    assert_eq!(log.read(9).unwrap().value, record(9).value);
+}
+
+#[test]
+fn age_retention_goes_by_record_timestamps_not_file_times() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config();
+    config.retention.max_age = Some(Duration::from_millis(200));
+    let mut log = Log::open(dir.path(), config).unwrap();
+    for i in 0..7 {
+        log.append(keyed("a", Some(&i.to_string()))).unwrap();
+    }
+    thread::sleep(Duration::from_millis(250));
+
+    // Compaction rewrites the old segments, giving their files a fresh
+    // modification time, but their records are as old as ever.
+    log.compact().unwrap();
+    log.enforce_retention().unwrap();
+    assert!(!dir.path().join("0.store").exists());
+    assert!(!dir.path().join("3.store").exists());
+    assert_eq!(log.lowest_offset(), 6);
+}
+
+#[test]
+fn retention_runs_in_the_background() {
+    let dir = TempDir::new().unwrap();
+    let mut config = config();
+    config.retention.max_age = Some(Duration::ZERO);
+    config.retention.check_interval = Duration::from_millis(10);
+    let mut log = Log::open(dir.path(), config).unwrap();
+    fill(&mut log);
+    let log = SharedLog::new(log);
+
+    wait_until("retention removes the oldest segment", || {
+        !dir.path().join("0.store").exists()
+    });
+    assert!(matches!(log.read(0), Err(Error::OffsetTruncated { .. })));
+    assert_eq!(log.read(9).unwrap().value, record(9).value);
+}
+
+#[test]
+fn truncate_removes_segments_below_lowest() {
+    let dir = TempDir::new().unwrap();
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    assert_eq!(log.highest_offset(), None);
+    fill(&mut log);
+    assert_eq!(log.lowest_offset(), 0);
+    assert_eq!(log.highest_offset(), Some(9));
+
+    // Offset 4 is still needed, so only the segment holding 0-2 goes.
+    log.truncate(4).unwrap();
+    assert_eq!(log.lowest_offset(), 3);
+    assert!(!dir.path().join("0.store").exists());
+    assert_eq!(log.read(4).unwrap().value, record(4).value);
+
+    // Truncating past the end keeps the active segment.
+    log.truncate(100).unwrap();
+    assert_eq!(log.lowest_offset(), 9);
+    assert_eq!(log.highest_offset(), Some(9));
+    assert_eq!(log.append(record(10)).unwrap(), 10);
+}
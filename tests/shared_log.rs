@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::thread;
+
+use bytes::Bytes;
+use chronicle::server::log::{Config, Error, Log, Record, SharedLog, SyncPolicy};
+use tempfile::TempDir;
+
+const WRITERS: usize = 16;
+const APPENDS_PER_WRITER: usize = 500;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn shared_log_is_send_and_sync() {
+    assert_send_sync::<SharedLog>();
+}
+
+#[test]
+fn concurrent_appends_assign_dense_unique_offsets() {
+    let dir = TempDir::new().unwrap();
+    let log = SharedLog::new(Log::open(dir.path(), Config::default()).unwrap());
+
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|writer| {
+            let log = log.clone();
+            thread::spawn(move || {
+                (0..APPENDS_PER_WRITER)
+                    .map(|i| {
+                        let value = Bytes::from(format!("{writer}-{i}"));
+                        let offset = log
+                            .append(Record {
+                                value: Some(value),
+                                ..Default::default()
+                            })
+                            .unwrap();
+                        (offset, writer, i)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+
+    let appended: Vec<(u64, usize, usize)> = writers
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .collect();
+
+    let total = (WRITERS * APPENDS_PER_WRITER) as u64;
+    let offsets: HashSet<u64> = appended.iter().map(|(offset, _, _)| *offset).collect();
+    assert_eq!(offsets.len() as u64, total);
+    assert_eq!(offsets, (0..total).collect());
+
+    for (offset, writer, i) in appended {
+        let record = log.read(offset).unwrap();
+        assert_eq!(record.offset, offset);
+        assert_eq!(record.value.unwrap(), format!("{writer}-{i}").into_bytes());
+    }
+    assert!(log.read(total).is_err());
+}
+
+#[test]
+fn readers_run_alongside_writers() {
+    let dir = TempDir::new().unwrap();
+    let log = SharedLog::new(Log::open(dir.path(), Config::default()).unwrap());
+    log.append(Record {
+        value: Some(Bytes::from_static(b"first")),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let readers: Vec<_> = (0..WRITERS)
+        .map(|_| {
+            let log = log.clone();
+            thread::spawn(move || {
+                for _ in 0..APPENDS_PER_WRITER {
+                    assert_eq!(log.read(0).unwrap().value.unwrap(), b"first"[..]);
+                }
+            })
+        })
+        .collect();
+
+    for i in 0..APPENDS_PER_WRITER {
+        let offset = log
+            .append(Record {
+                value: Some(i.to_string().into()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(offset, i as u64 + 1);
+    }
+
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
+
+#[test]
+fn closed_log_rejects_every_handle() {
+    let dir = TempDir::new().unwrap();
+    let log = SharedLog::new(Log::open(dir.path(), Config::default()).unwrap());
+    let other = log.clone();
+    log.append(Record {
+        value: Some(Bytes::from_static(b"first")),
+        ..Default::default()
+    })
+    .unwrap();
+
+    log.close().unwrap();
+    assert!(matches!(other.read(0), Err(Error::Closed)));
+    assert!(matches!(
+        other.append(Record {
+            value: Some(Bytes::from_static(b"second")),
+            ..Default::default()
+        }),
+        Err(Error::Closed)
+    ));
+    assert!(matches!(other.close(), Err(Error::Closed)));
+}
+
+#[test]
+fn group_commit_persists_every_acknowledged_append() {
+    let dir = TempDir::new().unwrap();
+    let config = Config {
+        sync: SyncPolicy::Always,
+        ..Config::default()
+    };
+    let log = SharedLog::new(Log::open(dir.path(), config.clone()).unwrap());
+
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|writer| {
+            let log = log.clone();
+            thread::spawn(move || {
+                (0..APPENDS_PER_WRITER / 10)
+                    .map(|i| {
+                        let value = Bytes::from(format!("{writer}-{i}"));
+                        (
+                            log.append(Record {
+                                value: Some(value),
+                                ..Default::default()
+                            })
+                            .unwrap(),
+                            writer,
+                            i,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let appended: Vec<(u64, usize, usize)> = writers
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .collect();
+    log.close().unwrap();
+
+    let reopened = Log::open(dir.path(), config).unwrap();
+    let offsets: HashSet<u64> = appended.iter().map(|(offset, _, _)| *offset).collect();
+    assert_eq!(offsets, (0..appended.len() as u64).collect());
+    for (offset, writer, i) in appended {
+        assert_eq!(
+            reopened.read(offset).unwrap().value.unwrap(),
+            format!("{writer}-{i}").into_bytes()
+        );
+    }
+}
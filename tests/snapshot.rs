@@ -0,0 +1,135 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chronicle::server::log::tiered::LocalObjectStore;
+use chronicle::server::log::{Config, Error, Log, SharedLog, TieringConfig};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{config, record};
+
+fn values(log: &Log) -> Vec<String> {
+    let (records, _) = log.read_range(log.lowest_offset(), 100, u64::MAX).unwrap();
+    records
+        .into_iter()
+        .map(|record| String::from_utf8(record.value.unwrap().to_vec()).unwrap())
+        .collect()
+}
+
+#[test]
+fn snapshot_restores_every_record_up_to_the_offset() {
+    let (dir, restored) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    for i in 0..7 {
+        log.append(record(i)).unwrap();
+    }
+    let snapshot = log.snapshot(4).unwrap();
+    // Appends carry on while the snapshot is outstanding.
+    log.append(record(7)).unwrap();
+    let mut archive: Vec<u8> = Vec::new();
+    let manifest = snapshot.write_to(&mut archive).unwrap();
+    assert_eq!(manifest.lowest_offset, 0);
+    assert_eq!(manifest.next_offset, 5);
+    assert_eq!(manifest.segments, [0, 3]);
+
+    let target = restored.path().join("data");
+    let mut copy = Log::restore(&archive[..], &target, config()).unwrap();
+    assert_eq!(
+        values(&copy),
+        (0..5).map(|i| format!("record-{i}")).collect::<Vec<_>>()
+    );
+    assert!(matches!(copy.read(5), Err(Error::OffsetOutOfRange(5))));
+    assert_eq!(copy.append(record(5)).unwrap(), 5);
+
+    // A snapshot past the end covers the whole log.
+    let mut archive: Vec<u8> = Vec::new();
+    let manifest = log
+        .snapshot(u64::MAX)
+        .unwrap()
+        .write_to(&mut archive)
+        .unwrap();
+    assert_eq!(manifest.next_offset, 8);
+    let copy = Log::restore(&archive[..], restored.path().join("full"), config()).unwrap();
+    assert_eq!(values(&copy).len(), 8);
+}
+
+#[test]
+fn snapshot_keeps_batches_whole() {
+    let (dir, restored) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    log.append_batch(vec![record(0), record(1), record(2)])
+        .unwrap();
+    let mut archive: Vec<u8> = Vec::new();
+    let manifest = log.snapshot(1).unwrap().write_to(&mut archive).unwrap();
+    assert_eq!(manifest.next_offset, 3);
+    let copy = Log::restore(&archive[..], restored.path().join("data"), config()).unwrap();
+    assert_eq!(values(&copy).len(), 3);
+}
+
+#[test]
+fn restore_rejects_bad_input_and_leaves_the_directory_alone() {
+    let (dir, restored) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let mut log = Log::open(dir.path(), config()).unwrap();
+    for i in 0..5 {
+        log.append(record(i)).unwrap();
+    }
+    let mut archive: Vec<u8> = Vec::new();
+    log.snapshot(u64::MAX)
+        .unwrap()
+        .write_to(&mut archive)
+        .unwrap();
+
+    // The target must be empty.
+    assert!(matches!(
+        Log::restore(&archive[..], dir.path(), config()),
+        Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::AlreadyExists
+    ));
+
+    // A truncated archive is missing records the manifest lists.
+    let target = restored.path().join("data");
+    let truncated = &archive[..archive.len() - 1536];
+    assert!(Log::restore(truncated, &target, config()).is_err());
+    assert!(!target.exists());
+    assert!(Log::restore(&b"not a snapshot"[..], &target, config()).is_err());
+    assert!(!target.exists());
+    assert_eq!(std::fs::read_dir(restored.path()).unwrap().count(), 0);
+
+    assert_eq!(
+        values(&Log::restore(&archive[..], &target, config()).unwrap()).len(),
+        5
+    );
+}
+
+#[test]
+fn snapshot_fetches_offloaded_segments() {
+    let (dir, remote, restored) = (
+        TempDir::new().unwrap(),
+        TempDir::new().unwrap(),
+        TempDir::new().unwrap(),
+    );
+    let mut config = config();
+    config.tiering = Some(TieringConfig {
+        store: Arc::new(LocalObjectStore::new(remote.path()).unwrap()),
+        hot_retention: Duration::ZERO,
+        check_interval: Duration::from_secs(60),
+    });
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    for i in 0..8 {
+        log.append(record(i)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(10));
+    log.offload().unwrap();
+    assert!(!dir.path().join("3.store").exists());
+
+    let log = SharedLog::new(log);
+    for upto in [4, 7] {
+        let mut archive: Vec<u8> = Vec::new();
+        let manifest = log.snapshot(upto).unwrap().write_to(&mut archive).unwrap();
+        assert_eq!(manifest.next_offset, upto + 1);
+        let target = restored.path().join(upto.to_string());
+        let copy = Log::restore(&archive[..], &target, Config::default()).unwrap();
+        assert_eq!(values(&copy).len() as u64, upto + 1);
+    }
+}
@@ -0,0 +1,254 @@
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use bytes::Bytes;
+use chronicle::server::log::tiered::{LocalObjectStore, ObjectStore};
+use chronicle::server::log::{Config, Error, Log, SharedLog, TieringConfig};
+use tempfile::TempDir;
+
+mod common;
+
+use common::{record, wait_until};
+
+// Three records per segment, so ten records span four segments, tiered to an
+// object store kept in `remote`.
+fn config(remote: &TempDir, hot_retention: Duration) -> Config {
+    let mut config = common::config();
+    config.tiering = Some(TieringConfig {
+        store: Arc::new(LocalObjectStore::new(remote.path()).unwrap()),
+        hot_retention,
+        check_interval: Duration::from_millis(10),
+    });
+    config
+}
+
+// Wraps the local object store, recording every download and holding uploads
+// back until released.
+#[derive(Debug)]
+struct Watched {
+    inner: LocalObjectStore,
+    downloads: Mutex<Vec<String>>,
+    uploads_held: Mutex<bool>,
+    uploads_started: Mutex<usize>,
+}
+
+impl Watched {
+    fn new(remote: &TempDir, hold_uploads: bool) -> Arc<Self> {
+        Arc::new(Self {
+            inner: LocalObjectStore::new(remote.path()).unwrap(),
+            downloads: Mutex::new(Vec::new()),
+            uploads_held: Mutex::new(hold_uploads),
+            uploads_started: Mutex::new(0),
+        })
+    }
+
+    fn downloads(&self) -> Vec<String> {
+        std::mem::take(&mut *self.downloads.lock().unwrap())
+    }
+}
+
+impl ObjectStore for Watched {
+    fn upload(&self, name: &str, from: &Path) -> io::Result<()> {
+        *self.uploads_started.lock().unwrap() += 1;
+        while *self.uploads_held.lock().unwrap() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        self.inner.upload(name, from)
+    }
+
+    fn download(&self, name: &str, to: &Path) -> io::Result<()> {
+        self.downloads.lock().unwrap().push(name.to_string());
+        self.inner.download(name, to)
+    }
+
+    fn delete(&self, name: &str) -> io::Result<()> {
+        self.inner.delete(name)
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        self.inner.list()
+    }
+}
+
+fn fill(log: &mut Log) -> Vec<u64> {
+    (0..10)
+        .map(|i| {
+            log.append(record(i)).unwrap();
+            log.read(i).unwrap().timestamp
+        })
+        .collect()
+}
+
+fn objects(remote: &TempDir) -> Vec<String> {
+    let mut names = LocalObjectStore::new(remote.path())
+        .unwrap()
+        .list()
+        .unwrap();
+    names.sort();
+    names
+}
+
+#[test]
+fn cold_segments_are_offloaded_and_fetched_back_on_read() {
+    let (dir, remote) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let config = config(&remote, Duration::ZERO);
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    let stamps = fill(&mut log);
+    thread::sleep(Duration::from_millis(10));
+    log.offload().unwrap();
+
+    // Every closed segment went to the object store and left local disk.
+    assert_eq!(objects(&remote).len(), 9);
+    for base_offset in [0, 3, 6] {
+        assert!(!dir.path().join(format!("{base_offset}.store")).exists());
+    }
+    assert!(dir.path().join("9.store").exists());
+
+    let check = |log: &Log| {
+        assert_eq!(log.lowest_offset(), 0);
+        for i in [1, 7, 4, 9] {
+            assert_eq!(log.read(i).unwrap().value.unwrap(), format!("record-{i}"));
+        }
+        let (records, next) = log.read_range(2, 5, u64::MAX).unwrap();
+        let offsets: Vec<u64> = records.iter().map(|record| record.offset).collect();
+        assert_eq!(offsets, [2, 3, 4, 5, 6]);
+        assert_eq!(next, 7);
+        assert_eq!(log.offset_for_time(0).unwrap(), 0);
+        assert_eq!(log.offset_for_time(stamps[9] + 1).unwrap(), 10);
+    };
+    check(&log);
+    log.close().unwrap();
+
+    // A restart finds the offloaded segments in the object store.
+    let mut log = Log::open(dir.path(), config).unwrap();
+    check(&log);
+    assert_eq!(log.append(record(10)).unwrap(), 10);
+}
+
+#[test]
+fn hot_segments_are_uploaded_but_kept_locally() {
+    let (dir, remote) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let mut log = Log::open(dir.path(), config(&remote, Duration::from_secs(3600))).unwrap();
+    fill(&mut log);
+    log.offload().unwrap();
+
+    assert!(objects(&remote).contains(&"3.store".to_string()));
+    assert!(!objects(&remote).contains(&"9.store".to_string()));
+    assert!(dir.path().join("3.store").exists());
+    assert_eq!(log.read(4).unwrap().value.unwrap(), "record-4");
+
+    // Compacting a segment re-uploads it on the next pass.
+    let mut keyed = record(10);
+    keyed.key = Some(Bytes::from_static(b"k"));
+    log.append(keyed.clone()).unwrap();
+    log.append(keyed).unwrap();
+    log.append(record(12)).unwrap();
+    log.offload().unwrap();
+    let before = std::fs::metadata(remote.path().join("9.store"))
+        .unwrap()
+        .len();
+    log.compact().unwrap();
+    log.offload().unwrap();
+    let after = std::fs::metadata(remote.path().join("9.store"))
+        .unwrap()
+        .len();
+    assert!(before > after);
+}
+
+#[test]
+fn truncation_and_retention_delete_offloaded_segments() {
+    let (dir, remote) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let mut config = config(&remote, Duration::ZERO);
+    let mut log = Log::open(dir.path(), config.clone()).unwrap();
+    fill(&mut log);
+    thread::sleep(Duration::from_millis(10));
+    log.offload().unwrap();
+
+    log.truncate(3).unwrap();
+    assert_eq!(log.lowest_offset(), 3);
+    assert!(matches!(log.read(0), Err(Error::OffsetTruncated { .. })));
+    assert!(!objects(&remote).contains(&"0.store".to_string()));
+    assert_eq!(log.read(5).unwrap().value.unwrap(), "record-5");
+    log.close().unwrap();
+
+    config.retention.max_age = Some(Duration::ZERO);
+    let mut log = Log::open(dir.path(), config).unwrap();
+    thread::sleep(Duration::from_millis(10));
+    log.enforce_retention().unwrap();
+    assert!(objects(&remote).is_empty());
+    assert_eq!(log.lowest_offset(), 9);
+}
+
+#[test]
+fn segments_are_offloaded_in_the_background() {
+    let (dir, remote) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let mut log = Log::open(dir.path(), config(&remote, Duration::ZERO)).unwrap();
+    fill(&mut log);
+    let log = SharedLog::new(log);
+
+    wait_until("the cold segment is offloaded", || {
+        !dir.path().join("6.store").exists()
+    });
+    assert_eq!(log.read(7).unwrap().value.unwrap(), "record-7");
+}
+
+#[test]
+fn fetched_segments_are_cached_and_seeks_only_probe_time_indexes() {
+    let (dir, remote) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let watched = Watched::new(&remote, false);
+    let mut config = config(&remote, Duration::ZERO);
+    config.tiering.as_mut().unwrap().store = watched.clone();
+    let mut log = Log::open(dir.path(), config).unwrap();
+    let stamps = fill(&mut log);
+    thread::sleep(Duration::from_millis(10));
+    log.offload().unwrap();
+
+    // Seeking into offloaded segments downloads time indexes, never stores.
+    let first = stamps.iter().position(|&stamp| stamp >= stamps[4]).unwrap();
+    assert_eq!(log.offset_for_time(stamps[4]).unwrap(), first as u64);
+    let probed = watched.downloads();
+    assert!(!probed.is_empty());
+    assert!(probed.iter().all(|name| name.ends_with(".timeindex")));
+
+    // Each offloaded segment is fetched once, however often it is read.
+    for _ in 0..3 {
+        for i in 0..9 {
+            assert_eq!(log.read(i).unwrap().value.unwrap(), format!("record-{i}"));
+        }
+        let (records, _) = log.read_range(0, 9, u64::MAX).unwrap();
+        assert_eq!(records.len(), 9);
+    }
+    let mut stores: Vec<String> = watched
+        .downloads()
+        .into_iter()
+        .filter(|name| name.ends_with(".store"))
+        .collect();
+    stores.sort();
+    assert_eq!(stores, ["0.store", "3.store", "6.store"]);
+}
+
+#[test]
+fn appends_and_reads_carry_on_while_segments_upload() {
+    let (dir, remote) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+    let watched = Watched::new(&remote, true);
+    let mut config = config(&remote, Duration::ZERO);
+    config.tiering.as_mut().unwrap().store = watched.clone();
+    let mut log = Log::open(dir.path(), config).unwrap();
+    fill(&mut log);
+    let log = SharedLog::new(log);
+
+    wait_until("an upload starts", || {
+        *watched.uploads_started.lock().unwrap() > 0
+    });
+    assert_eq!(log.append(record(10)).unwrap(), 10);
+    assert_eq!(log.read(1).unwrap().value.unwrap(), "record-1");
+
+    *watched.uploads_held.lock().unwrap() = false;
+    wait_until("the cold segment is offloaded", || {
+        !dir.path().join("6.store").exists()
+    });
+    assert_eq!(log.read(7).unwrap().value.unwrap(), "record-7");
+}